@@ -0,0 +1,8 @@
+async fn answer() -> u32 {
+    42
+}
+
+async fn main() -> Result<(), String> {
+    println!("The answer is {}", answer().await);
+    Ok(())
+}
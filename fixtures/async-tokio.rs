@@ -0,0 +1,6 @@
+//# tokio = "1"
+
+async fn main() {
+    tokio::task::yield_now().await;
+    println!("Hello from tokio!");
+}
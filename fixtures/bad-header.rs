@@ -0,0 +1,3 @@
+//# serde 1
+
+fn main() {}
@@ -0,0 +1,16 @@
+#![feature(test)]
+
+extern crate test;
+
+fn add(a: u64, b: u64) -> u64 {
+    a + b
+}
+
+fn main() {
+    println!("{}", add(1, 2));
+}
+
+#[bench]
+fn bench_add(b: &mut test::Bencher) {
+    b.iter(|| add(test::black_box(1), test::black_box(2)));
+}
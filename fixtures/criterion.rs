@@ -0,0 +1,8 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+fn add(c: &mut Criterion) {
+    c.bench_function("add", |b| b.iter(|| black_box(1) + black_box(2)));
+}
+
+criterion_group!(benches, add);
+criterion_main!(benches);
@@ -0,0 +1,6 @@
+//@ edition: 2015
+
+fn main() {
+    let async = 1;
+    println!("hello {}", async);
+}
@@ -0,0 +1,10 @@
+use std::hint::black_box;
+
+#[inline(never)]
+fn add(a: u32, b: u32) -> u32 {
+    a.wrapping_add(b)
+}
+
+fn main() {
+    println!("{}", add(black_box(1), black_box(2)));
+}
@@ -0,0 +1,3 @@
+fn main() {
+    println!("{}", std::env::var("CARGO_MANIFEST_DIR").is_ok());
+}
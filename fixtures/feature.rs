@@ -0,0 +1,6 @@
+#![feature(never_type)]
+
+fn main() {
+    let x: Result<u32, !> = Ok(1);
+    println!("{:?}", x);
+}
@@ -0,0 +1,8 @@
+fn double(x: u32) -> u32 {
+    return x * 2;
+}
+
+async fn main() {
+    let mut count = double(1);
+    println!("{}", count);
+}
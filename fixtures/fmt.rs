@@ -0,0 +1,7 @@
+#!/usr/bin/env cargo-play
+//# serde = {features=["derive"],version="1"}
+//#   rand = { version = "0.8" }
+//# serde = { version = "1", features = ["derive"] }
+use std::collections::HashMap;
+fn main() { let mut map=HashMap::new(); map.insert(1,2);
+    println!("{:?}",map); }
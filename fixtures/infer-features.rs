@@ -0,0 +1,12 @@
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+fn main() {
+    let point = Point { x: 1, y: 2 };
+    println!("{}", serde_json::to_string(&point).unwrap());
+}
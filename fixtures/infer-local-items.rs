@@ -0,0 +1,25 @@
+use std::collections::hash_map;
+
+mod shapes {
+    pub fn area(w: u32, h: u32) -> u32 {
+        w * h
+    }
+}
+
+enum Direction {
+    Left,
+    Right,
+}
+
+fn main() {
+    use Direction::*;
+
+    let mut map = hash_map::HashMap::new();
+    map.insert("area", shapes::area(2, 3));
+    for direction in [Left, Right] {
+        match direction {
+            Left => println!("left {}", map["area"]),
+            Right => println!("right"),
+        }
+    }
+}
@@ -0,0 +1,8 @@
+mod utils;
+
+use utils::greet;
+
+fn main() {
+    greet();
+    utils::greet();
+}
@@ -0,0 +1,6 @@
+use regex_syntax::Parser;
+
+fn main() {
+    let hir = Parser::new().parse("a|b").unwrap();
+    println!("{:?}", hir.kind());
+}
@@ -0,0 +1,5 @@
+//# tokio = "1"
+
+async fn main() {
+    println!("Hello World!");
+}
@@ -0,0 +1,9 @@
+//# rand = "0.8"
+//# itoa = "1"
+//# broken =
+
+mod helper;
+
+fn main() {
+    println!("{}", helper::roll() + rand::random::<u8>() as u32);
+}
@@ -0,0 +1,5 @@
+//# rand = "0.7"
+
+pub fn roll() -> u32 {
+    4
+}
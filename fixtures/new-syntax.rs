@@ -0,0 +1,8 @@
+fn one() -> impl Sized + use<> {
+    1
+}
+
+fn main() {
+    let _ = one();
+    println!("Hello World!");
+}
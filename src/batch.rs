@@ -0,0 +1,305 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use log::debug;
+use serde::Serialize;
+
+use crate::errors::CargoPlayError;
+use crate::options::{Options, RunAllOptions};
+use crate::steps::*;
+
+#[derive(Debug, Serialize)]
+struct ScriptResult {
+    script: PathBuf,
+    passed: bool,
+    exit_code: Option<i32>,
+    duration: f64,
+    stdout: String,
+    stderr: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    passed: usize,
+    failed: usize,
+    duration: f64,
+    scripts: &'a [ScriptResult],
+}
+
+/// Builds and runs every script matched by `options.targets`, returning the exit code of the
+/// whole run.
+pub fn run_all(options: RunAllOptions) -> Result<i32, CargoPlayError> {
+    let scripts = expand_targets(&options.targets)?;
+    if scripts.is_empty() {
+        return Err(CargoPlayError::NoScriptsMatched);
+    }
+
+    let jobs = options
+        .jobs
+        .or_else(|| std::thread::available_parallelism().ok().map(Into::into))
+        .unwrap_or(1)
+        .max(1);
+    println!("running {} scripts with {} jobs", scripts.len(), jobs);
+
+    let started = Instant::now();
+    let queue = Mutex::new(scripts.iter().enumerate().collect::<VecDeque<_>>());
+    let results = Mutex::new(Vec::with_capacity(scripts.len()));
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.min(scripts.len()) {
+            scope.spawn(|| loop {
+                let next = queue.lock().unwrap().pop_front();
+                let (index, script) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+                let result = run_script(&options, script);
+                results.lock().unwrap().push((index, result));
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _)| *index);
+    let results: Vec<ScriptResult> = results.into_iter().map(|(_, result)| result).collect();
+    let duration = started.elapsed().as_secs_f64();
+
+    print_summary(&results, duration);
+
+    if let Some(path) = &options.junit {
+        File::create(path)?.write_all(junit_report(&results, duration).as_bytes())?;
+    }
+
+    if let Some(path) = &options.json {
+        let report = Report {
+            passed: results.iter().filter(|r| r.passed).count(),
+            failed: results.iter().filter(|r| !r.passed).count(),
+            duration,
+            scripts: &results,
+        };
+        serde_json::to_writer_pretty(File::create(path)?, &report)
+            .map_err(CargoPlayError::from_serde)?;
+    }
+
+    Ok(if results.iter().all(|r| r.passed) {
+        0
+    } else {
+        1
+    })
+}
+
+fn run_script(options: &RunAllOptions, script: &Path) -> ScriptResult {
+    let started = Instant::now();
+    let opt = Options {
+        src: vec![script.to_path_buf()],
+        edition: options.edition.clone(),
+        release: options.release,
+        infer: options.infer,
+        quiet: true,
+        ..Default::default()
+    };
+    let temp = temp_dir(opt.temp_dirname());
+    debug!("Running {:?} in {:?}", script, temp);
+
+    let output = read_files(&opt.src).and_then(|files| {
+        let opt = &Options {
+            toolchain: nightly_toolchain(&opt, None, &files)?,
+            ..opt.clone()
+        };
+        prepare_project(opt, &temp, None, &files)?;
+        Ok(cargo_command(opt, &temp).stdin(Stdio::null()).output()?)
+    });
+
+    let duration = started.elapsed().as_secs_f64();
+    match output {
+        Ok(output) => ScriptResult {
+            script: script.to_path_buf(),
+            passed: output.status.success(),
+            exit_code: output.status.code(),
+            duration,
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        },
+        Err(err) => ScriptResult {
+            script: script.to_path_buf(),
+            passed: false,
+            exit_code: None,
+            duration,
+            stdout: String::new(),
+            stderr: err.chain().to_string(),
+        },
+    }
+}
+
+fn print_summary(results: &[ScriptResult], duration: f64) {
+    for result in results.iter().filter(|r| !r.passed) {
+        println!("\n---- {} ----", result.script.display());
+        print!("{}", result.stdout);
+        eprint!("{}", result.stderr);
+    }
+
+    println!();
+    for result in results {
+        let status = match (result.passed, result.exit_code) {
+            (true, _) => "PASS".to_string(),
+            (false, Some(code)) => format!("FAIL ({})", code),
+            (false, None) => "FAIL".to_string(),
+        };
+        println!(
+            "{:<10} {:>8.2}s  {}",
+            status,
+            result.duration,
+            result.script.display()
+        );
+    }
+
+    let failed = results.iter().filter(|r| !r.passed).count();
+    println!(
+        "\n{} passed; {} failed; finished in {:.2}s",
+        results.len() - failed,
+        failed,
+        duration
+    );
+}
+
+fn junit_report(results: &[ScriptResult], duration: f64) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml += &format!(
+        "<testsuites>\n  <testsuite name=\"cargo-play\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n",
+        results.len(),
+        results.iter().filter(|r| !r.passed).count(),
+        duration
+    );
+
+    for result in results {
+        xml += &format!(
+            "    <testcase name=\"{}\" classname=\"cargo-play\" time=\"{:.3}\">\n",
+            xml_escape(&result.script.to_string_lossy()),
+            result.duration
+        );
+        if !result.passed {
+            let message = match result.exit_code {
+                Some(code) => format!("exit code {}", code),
+                None => "failed to run".to_string(),
+            };
+            xml += &format!("      <failure message=\"{}\"/>\n", message);
+        }
+        xml += &format!(
+            "      <system-out>{}</system-out>\n      <system-err>{}</system-err>\n    </testcase>\n",
+            xml_escape(&result.stdout),
+            xml_escape(&result.stderr)
+        );
+    }
+
+    xml + "  </testsuite>\n</testsuites>\n"
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Resolves the targets to a sorted list of absolute script paths. A target is a file, a
+/// directory (searched recursively for `.rs` files) or a glob pattern supporting `*`, `?` and `**`.
+fn expand_targets(targets: &[String]) -> Result<Vec<PathBuf>, CargoPlayError> {
+    let mut scripts = Vec::new();
+
+    for target in targets {
+        let path = Path::new(target);
+        if path.is_file() {
+            scripts.push(path.to_path_buf());
+        } else if path.is_dir() {
+            scripts.extend(
+                walk(path)?
+                    .into_iter()
+                    .filter(|p| p.extension().is_some_and(|ext| ext == "rs")),
+            );
+        } else {
+            let pattern: Vec<String> = path
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            let literal = pattern
+                .iter()
+                .take_while(|c| !c.contains(['*', '?']))
+                .count();
+            let root: PathBuf = path.components().take(literal).collect();
+            let root = if literal == 0 {
+                PathBuf::from(".")
+            } else {
+                root
+            };
+            let pattern: Vec<&str> = pattern[literal..].iter().map(AsRef::as_ref).collect();
+
+            if root.is_dir() {
+                for file in walk(&root)? {
+                    let relative: Vec<String> = file
+                        .strip_prefix(&root)
+                        .unwrap_or(&file)
+                        .components()
+                        .filter(|c| matches!(c, Component::Normal(_)))
+                        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                        .collect();
+                    let relative: Vec<&str> = relative.iter().map(AsRef::as_ref).collect();
+                    if glob_match(&pattern, &relative) {
+                        scripts.push(file);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut scripts = scripts
+        .into_iter()
+        .map(|p| p.canonicalize())
+        .collect::<Result<Vec<_>, _>>()?;
+    scripts.sort();
+    scripts.dedup();
+    Ok(scripts)
+}
+
+fn walk(dir: &Path) -> Result<Vec<PathBuf>, CargoPlayError> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(walk(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+fn glob_match(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|i| glob_match(rest, &path[i..])),
+        Some((segment, rest)) => match path.split_first() {
+            Some((first, remaining)) => {
+                wildcard_match(segment.as_bytes(), first.as_bytes()) && glob_match(rest, remaining)
+            }
+            None => false,
+        },
+    }
+}
+
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some((b'*', rest)), _) => {
+            wildcard_match(rest, name) || (!name.is_empty() && wildcard_match(pattern, &name[1..]))
+        }
+        (Some((b'?', rest)), Some((_, remaining))) => wildcard_match(rest, remaining),
+        (Some((p, rest)), Some((n, remaining))) if p == n => wildcard_match(rest, remaining),
+        _ => false,
+    }
+}
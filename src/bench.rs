@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use crate::errors::{CargoPlayError, EXIT_BUILD};
+use crate::infer::BenchHarness;
+use crate::options::Options;
+use crate::steps::*;
+
+/// Nanoseconds per iteration of each benchmark, by name.
+type Results = BTreeMap<String, f64>;
+
+/// Builds and runs the benchmarks of the generated project with `cargo bench`. Criterion saves and
+/// compares baselines by itself, while the results of `#[bench]` functions are saved in the
+/// project's cache directory and compared here.
+pub fn bench(options: &Options, project: &Path, entry: &str) -> Result<i32, CargoPlayError> {
+    let harness = bench_harness(entry)?;
+    let baseline = match (harness, options.baseline.as_ref()) {
+        (BenchHarness::Libtest, Some(name)) => Some(load_baseline(project, name)?),
+        _ => None,
+    };
+
+    let build = cargo_subcommand(options, project, "bench")
+        .arg("--no-run")
+        .stderr(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .status()?;
+    if !build.success() {
+        return Ok(EXIT_BUILD);
+    }
+
+    let mut cargo = cargo_command(options, project);
+    if harness == BenchHarness::Criterion {
+        if let Some(name) = options.baseline.as_ref() {
+            cargo.arg("--baseline").arg(name);
+        }
+        if let Some(name) = options.save_baseline.as_ref() {
+            cargo.arg("--save-baseline").arg(name);
+        }
+    }
+    if harness == BenchHarness::Criterion || (baseline.is_none() && options.save_baseline.is_none())
+    {
+        let status = cargo
+            .stderr(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .status()?;
+        return Ok(exit_code(status));
+    }
+
+    // libtest prints one line per benchmark, which are echoed as they come
+    let mut child = cargo
+        .stderr(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut results = Results::new();
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines() {
+            let line = line?;
+            println!("{}", line);
+            if let Some((name, nanoseconds)) = parse_result(&line) {
+                results.insert(name, nanoseconds);
+            }
+        }
+    }
+    let status = child.wait()?;
+
+    if let Some(name) = options.save_baseline.as_ref() {
+        let path = baseline_path(project, name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let content = serde_json::to_string_pretty(&results).map_err(CargoPlayError::from_serde)?;
+        fs::write(path, content)?;
+        eprintln!("Saved baseline {:?}", name);
+    }
+    if let Some(baseline) = baseline {
+        print_comparison(&baseline, &results);
+    }
+
+    Ok(exit_code(status))
+}
+
+fn baseline_path(project: &Path, name: &str) -> PathBuf {
+    project.join("baselines").join(format!("{}.json", name))
+}
+
+fn load_baseline(project: &Path, name: &str) -> Result<Results, CargoPlayError> {
+    let content = fs::read_to_string(baseline_path(project, name))
+        .map_err(|_| CargoPlayError::BaselineNotFound(name.into()))?;
+    serde_json::from_str(&content).map_err(CargoPlayError::from_serde)
+}
+
+/// The name and nanoseconds per iteration of a libtest result line, like `test add ... bench:
+/// 1,234 ns/iter (+/- 56)`.
+fn parse_result(line: &str) -> Option<(String, f64)> {
+    let (name, result) = line.strip_prefix("test ")?.split_once(" ... bench:")?;
+    let nanoseconds = result
+        .split_whitespace()
+        .next()?
+        .replace(',', "")
+        .parse()
+        .ok()?;
+    Some((name.to_string(), nanoseconds))
+}
+
+fn print_comparison(baseline: &Results, results: &Results) {
+    let rows: Vec<[String; 4]> = results
+        .iter()
+        .map(|(name, current)| {
+            let (before, change) = match baseline.get(name) {
+                Some(before) if *before > 0.0 => (
+                    format!("{:.2} ns/iter", before),
+                    format!("{:+.2}%", (current - before) / before * 100.0),
+                ),
+                Some(before) => (format!("{:.2} ns/iter", before), String::new()),
+                None => (String::new(), "new".into()),
+            };
+            [
+                name.clone(),
+                before,
+                format!("{:.2} ns/iter", current),
+                change,
+            ]
+        })
+        .collect();
+
+    let header = ["benchmark", "baseline", "current", "change"].map(String::from);
+    let widths: Vec<usize> = (0..header.len())
+        .map(|column| {
+            rows.iter()
+                .chain(Some(&header))
+                .map(|row| row[column].len())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    println!();
+    for row in Some(&header).into_iter().chain(&rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_result() {
+        assert_eq!(
+            parse_result("test add ... bench:       1,234 ns/iter (+/- 56)"),
+            Some(("add".into(), 1234.0))
+        );
+        assert_eq!(
+            parse_result("test benches::add ... bench:          0.52 ns/iter (+/- 0.01)"),
+            Some(("benches::add".into(), 0.52))
+        );
+        assert_eq!(parse_result("test add ... ok"), None);
+    }
+}
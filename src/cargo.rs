@@ -1,16 +1,113 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
 
 use serde::Serialize;
 use toml::value::{Table, Value};
 
-use crate::errors::CargoPlayError;
+use crate::errors::{CargoPlayError, HeaderDiagnostic};
+use crate::infer::{BenchHarness, InferredCrate};
 use crate::options::RustEdition;
+use crate::steps::Header;
+
+/// Guesses the header that was meant by a malformed one, e.g. `serde = "1"` for `serde 1`,
+/// `serde = 1.0`, `serde@1` or `serde`.
+fn suggest(text: &str) -> Option<String> {
+    let is_name = |s: &str| {
+        !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+
+    let text = text.trim();
+    if is_name(text) {
+        return Some(format!("{} = \"*\"", text));
+    }
+
+    let (name, requirement) =
+        text.split_once(|c: char| c == '=' || c == '@' || c.is_whitespace())?;
+    let requirement = requirement
+        .trim_start_matches(|c: char| c == '=' || c.is_whitespace())
+        .trim_end()
+        .trim_matches(|c| c == '"' || c == '\'');
+    let is_requirement = !requirement.is_empty()
+        && requirement
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ".*^~<>=, -+".contains(c));
+
+    if is_name(name) && is_requirement {
+        Some(format!("{} = \"{}\"", name, requirement))
+    } else {
+        None
+    }
+}
+
+/// Parses a `//#` header into the dependencies it declares. `inputs` names the inputs the header
+/// may come from.
+pub(crate) fn parse_header(header: &Header, inputs: &[String]) -> Result<Table, CargoPlayError> {
+    let text = &header.text;
+    let offset = header.raw.len() - text.len();
+    let diagnostic = |message: String, (start, end): (usize, usize)| {
+        CargoPlayError::HeaderError(HeaderDiagnostic {
+            message,
+            file: inputs[header.source].clone(),
+            line: header.line,
+            snippet: header.raw.clone(),
+            span: (offset + start, offset + end),
+            suggestion: suggest(text),
+        })
+    };
+
+    let table = match text.parse::<Value>() {
+        Ok(Value::Table(table)) => table,
+        Ok(_) => {
+            return Err(diagnostic(
+                "expected a dependency declaration".into(),
+                (0, text.len()),
+            ))
+        }
+        Err(err) => {
+            let message = err.to_string();
+            let message = message.split(" at line ").next().unwrap_or(&message);
+
+            // point at the character the error is reported at, when it's known
+            let span = match err.line_col() {
+                Some((0, column)) => {
+                    let mut start = column.min(text.len());
+                    while !text.is_char_boundary(start) {
+                        start -= 1;
+                    }
+                    let end = text[start..]
+                        .chars()
+                        .next()
+                        .map_or(start, |c| start + c.len_utf8());
+                    (start, end)
+                }
+                _ => (0, text.len()),
+            };
+            return Err(diagnostic(message.into(), span));
+        }
+    };
+
+    if let Some((name, _)) = table
+        .iter()
+        .find(|(_, value)| !value.is_str() && !value.is_table())
+    {
+        return Err(diagnostic(
+            format!("`{}` needs a version requirement string or a table", name),
+            (0, text.len()),
+        ));
+    }
+
+    Ok(table)
+}
 
 #[derive(Clone, Debug, Serialize)]
 struct CargoPackage {
     name: String,
     version: String,
     edition: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    autobins: Option<bool>,
 }
 
 impl CargoPackage {
@@ -19,45 +116,93 @@ impl CargoPackage {
             name: name.to_lowercase(),
             version: "0.1.0".into(),
             edition: edition.into(),
+            autobins: None,
         }
     }
 }
 
+#[derive(Clone, Debug, Serialize)]
+struct CargoTarget {
+    name: String,
+    path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    harness: Option<bool>,
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub(crate) struct CargoManifest {
     package: CargoPackage,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    bin: Vec<CargoTarget>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    bench: Vec<CargoTarget>,
     #[serde(serialize_with = "toml::ser::tables_last")]
     dependencies: Table,
+    #[serde(
+        rename = "dev-dependencies",
+        skip_serializing_if = "Table::is_empty",
+        serialize_with = "toml::ser::tables_last"
+    )]
+    dev_dependencies: Table,
+    /// The dependencies added by `add_infers`, by package name
+    #[serde(skip)]
+    inferred: BTreeMap<String, InferredCrate>,
 }
 
 impl CargoManifest {
+    /// Creates the manifest declaring the dependencies of the `headers`, which come from the
+    /// inputs named by `inputs`.
     pub(crate) fn new(
         name: String,
-        dependencies: Vec<String>,
+        headers: &[Header],
+        inputs: &[String],
         edition: RustEdition,
     ) -> Result<Self, CargoPlayError> {
-        let dependencies = dependencies
-            .into_iter()
-            .map(|dependency| dependency.parse::<toml::Value>())
-            .collect::<Result<Vec<toml::Value>, _>>()
-            .map_err(CargoPlayError::from_serde)?;
-
-        if dependencies.iter().any(|d| !d.is_table()) {
-            return Err(CargoPlayError::ParseError("format error!".into()));
+        let mut dependencies = Table::new();
+        for header in headers {
+            dependencies.extend(parse_header(header, inputs)?);
         }
 
-        let dependencies: Table = dependencies
-            .into_iter()
-            .map(|d| d.try_into::<Table>().unwrap().into_iter())
-            .flatten()
-            .collect();
-
         Ok(Self {
             package: CargoPackage::new(name, edition),
+            bin: Vec::new(),
+            bench: Vec::new(),
             dependencies,
+            dev_dependencies: Table::new(),
+            inferred: BTreeMap::new(),
         })
     }
 
+    /// Points the binary target at `path` instead of the default `src/main.rs`.
+    pub(crate) fn set_bin_path(&mut self, path: PathBuf) {
+        self.bin = vec![CargoTarget {
+            name: self.package.name.clone(),
+            path,
+            harness: None,
+        }];
+    }
+
+    /// Makes `src/main.rs` a benchmark target run by `harness` instead of a binary. `criterion` is
+    /// added as a development dependency, unless the headers already declare it.
+    pub(crate) fn set_bench(&mut self, harness: BenchHarness, criterion: Option<InferredCrate>) {
+        // the binary would be built too, without the development dependencies
+        self.package.autobins = Some(false);
+        self.bench = vec![CargoTarget {
+            name: self.package.name.clone(),
+            path: PathBuf::from("src").join("main.rs"),
+            harness: Some(harness == BenchHarness::Libtest),
+        }];
+
+        if let Some(criterion) = criterion {
+            if !self.normalized_dependencies().contains("criterion") {
+                self.dev_dependencies.insert(
+                    criterion.package.clone(),
+                    Self::inferred_dependency(&criterion),
+                );
+            }
+        }
+    }
+
     fn normalize_crate_name(name: &str) -> String {
         name.replace("-", "_")
     }
@@ -70,16 +215,131 @@ impl CargoManifest {
             .collect()
     }
 
-    pub(crate) fn add_infers(&mut self, infers: HashSet<String>) {
-        let existing = self.normalized_dependencies();
+    /// A version requirement, or a table when features need to be enabled.
+    fn inferred_dependency(infer: &InferredCrate) -> Value {
+        let version = Value::String(infer.requirement());
+        if infer.features.is_empty() {
+            return version;
+        }
 
-        // we don't need to normalize crate name here (in filter) since it's impossible to have
-        // dash in use statments.
-        self.dependencies.extend(
-            infers
-                .into_iter()
-                .filter(|key| !existing.contains(key))
-                .map(|key| (key, Value::String("*".into()))),
+        let mut table = Table::new();
+        table.insert("version".into(), version);
+        table.insert(
+            "features".into(),
+            Value::Array(infer.features.iter().cloned().map(Value::String).collect()),
         );
+        Value::Table(table)
+    }
+
+    /// Adds the inferred crates, keyed by the name used in the sources, unless the headers already
+    /// declare them under either that name or their package name.
+    pub(crate) fn add_infers(&mut self, infers: HashMap<String, InferredCrate>) {
+        let existing = self.normalized_dependencies();
+
+        // we don't need to normalize the used name here (in filter) since it's impossible to
+        // have dash in use statments.
+        for (name, infer) in infers {
+            if existing.contains(&name)
+                || existing.contains(&Self::normalize_crate_name(&infer.package))
+            {
+                continue;
+            }
+
+            self.dependencies
+                .insert(infer.package.clone(), Self::inferred_dependency(&infer));
+            self.inferred.insert(infer.package.clone(), infer);
+        }
+    }
+
+    /// Enables `features` of the dependency declared as `name`, for generated code that relies on
+    /// them. Nothing happens when there is no such dependency.
+    pub(crate) fn enable_features(&mut self, name: &str, features: &[&str]) {
+        let value = match self
+            .dependencies
+            .iter_mut()
+            .find(|(key, _)| Self::normalize_crate_name(key) == name)
+        {
+            Some((_, value)) => value,
+            None => return,
+        };
+
+        if let Value::String(version) = value {
+            let mut table = Table::new();
+            table.insert("version".into(), Value::String(version.clone()));
+            *value = Value::Table(table);
+        }
+        if let Value::Table(table) = value {
+            let enabled = table
+                .entry("features")
+                .or_insert_with(|| Value::Array(Vec::new()));
+            if let Value::Array(enabled) = enabled {
+                for feature in features {
+                    let feature = Value::String(feature.to_string());
+                    if !enabled.contains(&feature) {
+                        enabled.push(feature);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The inferred dependencies as `//#` header lines.
+    pub(crate) fn inferred_headers(&self) -> Vec<String> {
+        self.inferred
+            .iter()
+            .map(|(package, infer)| {
+                let requirement = format!("{:?}", infer.requirement());
+                if infer.features.is_empty() {
+                    return format!("{} = {}", package, requirement);
+                }
+
+                let features: Vec<String> = infer
+                    .features
+                    .iter()
+                    .map(|feature| format!("{:?}", feature))
+                    .collect();
+                format!(
+                    "{} = {{ version = {}, features = [{}] }}",
+                    package,
+                    requirement,
+                    features.join(", ")
+                )
+            })
+            .collect()
+    }
+
+    /// Renders the manifest with a comment after each dependency telling whether it was declared
+    /// in the headers or inferred, in which case `describe` explains why.
+    pub(crate) fn to_annotated_string(
+        &self,
+        describe: impl Fn(&InferredCrate) -> String,
+    ) -> Result<String, CargoPlayError> {
+        let content = toml::to_string(self).map_err(CargoPlayError::from_serde)?;
+
+        let annotate = |line: &str, key: &str| match self.inferred.get(key.trim_matches('"')) {
+            Some(infer) => format!("{} # inferred: {}", line, describe(infer)),
+            None => format!("{} # from //# headers", line),
+        };
+
+        let mut section = "";
+        let mut output = String::with_capacity(content.len());
+        for line in content.lines() {
+            let annotated = if line.starts_with('[') {
+                section = line.trim_matches(|c| c == '[' || c == ']');
+                match section.strip_prefix("dependencies.") {
+                    Some(key) => annotate(line, key),
+                    None => line.into(),
+                }
+            } else {
+                match line.split_once('=') {
+                    Some((key, _)) if section == "dependencies" => annotate(line, key.trim()),
+                    _ => line.into(),
+                }
+            };
+            output.push_str(&annotated);
+            output.push('\n');
+        }
+
+        Ok(output)
     }
 }
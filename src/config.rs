@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::errors::CargoPlayError;
+
+/// User configuration, read from the file named by `$CARGO_PLAY_CONFIG` or from
+/// `cargo-play/config.toml` in the user's configuration directory.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Config {
+    /// Packages providing the crates used in the sources, when the names differ
+    /// (e.g. `md5 = "md-5"`)
+    pub crates: HashMap<String, String>,
+    /// Features to enable when the sources use a crate in a certain way
+    pub features: Vec<FeatureRule>,
+    /// Runtime driving an `async fn main` when the script doesn't depend on one
+    pub async_runtime: Option<String>,
+    /// Toolchain building the scripts that use `#![feature]` (e.g. `nightly-2024-05-01`) instead
+    /// of the latest nightly
+    pub nightly_toolchain: Option<String>,
+}
+
+/// Enables `features` of the crate used as `name` when the sources use any of the given derive
+/// macro, attribute macro or path (including the paths below it).
+#[derive(Debug, Default, Deserialize)]
+pub struct FeatureRule {
+    #[serde(rename = "crate")]
+    pub name: String,
+    pub derive: Option<String>,
+    pub attribute: Option<String>,
+    pub path: Option<String>,
+    pub features: Vec<String>,
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        if let Some(path) = env::var_os("CARGO_PLAY_CONFIG") {
+            return Some(path.into());
+        }
+
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+            .map(|dir| dir.join("cargo-play").join("config.toml"))
+    }
+
+    /// Loads the configuration, falling back to the defaults when there is no file.
+    pub fn load() -> Result<Self, CargoPlayError> {
+        match Self::path() {
+            Some(path) if path.is_file() => toml::from_str(&std::fs::read_to_string(&path)?)
+                .map_err(|err| CargoPlayError::ConfigError(path, err)),
+            _ => Ok(Self::default()),
+        }
+    }
+}
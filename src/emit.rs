@@ -0,0 +1,288 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use serde_json::Value;
+
+use crate::errors::{CargoPlayError, EXIT_BUILD};
+use crate::options::{Emit, Options};
+use crate::steps::*;
+
+impl Emit {
+    /// Flags making rustc produce the output. A single codegen unit keeps the code in one file.
+    fn rustc_args(self) -> &'static [&'static str] {
+        match self {
+            Emit::Asm | Emit::Wasm => &["--emit", "asm", "-C", "codegen-units=1"],
+            Emit::LlvmIr => &["--emit", "llvm-ir", "-C", "codegen-units=1"],
+            Emit::Mir => &["--emit", "mir"],
+            Emit::Hir => &["-Zunpretty=hir"],
+        }
+    }
+
+    /// Extension of the file rustc writes next to the other build outputs, `None` when the
+    /// output is printed instead.
+    fn extension(self) -> Option<&'static str> {
+        match self {
+            Emit::Asm | Emit::Wasm => Some("s"),
+            Emit::LlvmIr => Some("ll"),
+            Emit::Mir => Some("mir"),
+            Emit::Hir => None,
+        }
+    }
+}
+
+/// Builds the generated project with `cargo rustc` and prints what the compiler emitted, or only
+/// the function named by `--filter`, with symbols demangled.
+pub fn emit(options: &Options, project: &Path, emit: Emit) -> Result<i32, CargoPlayError> {
+    let mut cargo = cargo_subcommand(options, project, "rustc");
+    cargo.arg("--bin").arg(options.package_name());
+    if emit == Emit::Wasm {
+        cargo.arg("--target").arg("wasm32-unknown-unknown");
+    }
+    let output = cargo
+        .arg("--message-format")
+        .arg("json-render-diagnostics")
+        .arg("--")
+        .args(emit.rustc_args())
+        .stderr(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .output()?;
+    if !output.status.success() {
+        return Ok(EXIT_BUILD);
+    }
+
+    // Cargo's JSON messages are interleaved with what rustc printed itself
+    let mut printed = String::new();
+    let mut deps = None;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        match serde_json::from_str::<Value>(line) {
+            Ok(message) if message.get("reason").is_some() => {
+                if let Some(executable) = message["executable"].as_str() {
+                    deps = Path::new(executable).parent().map(|dir| dir.join("deps"));
+                }
+            }
+            _ => {
+                printed.push_str(line);
+                printed.push('\n');
+            }
+        }
+    }
+
+    let text = match (emit.extension(), deps) {
+        (None, _) => printed,
+        (Some(extension), Some(deps)) => {
+            fs::read_to_string(emitted_file(&deps, &options.package_name(), extension)?)?
+        }
+        (Some(_), None) => {
+            return Err(CargoPlayError::_message(
+                "Cargo didn't report where the program was built",
+            ))
+        }
+    };
+    let text = demangle(&text);
+
+    match options.filter.as_ref() {
+        Some(name) => {
+            let functions = find_functions(emit, &text, name);
+            if functions.is_empty() {
+                return Err(CargoPlayError::FunctionNotFound(name.clone()));
+            }
+            print!("{}", functions.join("\n"));
+        }
+        None => print!("{}", text),
+    }
+    Ok(0)
+}
+
+/// The newest file with `extension` rustc wrote for the crate in Cargo's `deps` directory, named
+/// after the crate and a hash of the build configuration.
+fn emitted_file(deps: &Path, package: &str, extension: &str) -> Result<PathBuf, CargoPlayError> {
+    let prefix = format!("{}-", package.replace('-', "_"));
+    fs::read_dir(deps)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension().is_some_and(|ext| ext == extension)
+                && path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with(&prefix))
+        })
+        .max_by_key(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .ok_or_else(|| {
+            CargoPlayError::from(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no .{} file in {}", extension, deps.display()),
+            ))
+        })
+}
+
+/// Replaces the mangled Rust symbols found in `text` by their demangled paths, without hashes.
+fn demangle(text: &str) -> String {
+    fn flush(result: &mut String, symbol: &mut String) {
+        let mangled =
+            symbol.starts_with("_ZN") || symbol.starts_with("__ZN") || symbol.starts_with("_R");
+        match rustc_demangle::try_demangle(symbol) {
+            Ok(demangled) if mangled => result.push_str(&format!("{:#}", demangled)),
+            _ => result.push_str(symbol),
+        }
+        symbol.clear();
+    }
+
+    let mut result = String::with_capacity(text.len());
+    let mut symbol = String::new();
+    for c in text.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' || c == '$' || c == '.' {
+            symbol.push(c);
+        } else {
+            flush(&mut result, &mut symbol);
+            result.push(c);
+        }
+    }
+    flush(&mut result, &mut symbol);
+    result
+}
+
+/// Whether the function `symbol` is the one asked for by `name`, either exactly or as the last
+/// segments of its path.
+fn is_named(symbol: &str, name: &str) -> bool {
+    symbol == name || symbol.ends_with(&format!("::{}", name))
+}
+
+/// Finds every function called `name` in the (demangled) output. Functions whose path ends with
+/// `name` win over the ones named exactly so, like the C `main` calling the Rust one.
+fn find_functions(emit: Emit, text: &str, name: &str) -> Vec<String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut found: Vec<(String, String)> = Vec::new();
+
+    let mut index = 0;
+    while index < lines.len() {
+        let symbol = match emit {
+            Emit::Asm | Emit::Wasm => asm_label(lines[index]),
+            Emit::LlvmIr => llvm_function(lines[index]),
+            Emit::Mir | Emit::Hir => rust_function(lines[index]),
+        };
+        let symbol = match symbol {
+            Some(symbol) if is_named(symbol, name) => symbol.to_string(),
+            _ => {
+                index += 1;
+                continue;
+            }
+        };
+
+        let one_line = matches!(emit, Emit::Mir | Emit::Hir) && lines[index].ends_with('}');
+        let end = match (index + 1..lines.len()).find(|&end| is_end(emit, lines[index], lines[end]))
+        {
+            _ if one_line => index + 1,
+            Some(end) if closes(emit, lines[end]) => end + 1,
+            Some(end) => end,
+            None => lines.len(),
+        };
+        let mut block = lines[index..end].join("\n");
+        block.push('\n');
+        found.push((symbol, block));
+        index = end;
+    }
+
+    let qualified = found.iter().any(|(symbol, _)| symbol != name);
+    found
+        .into_iter()
+        .filter(|(symbol, _)| !qualified || symbol != name)
+        .map(|(_, block)| block)
+        .collect()
+}
+
+/// The function defined by an assembly label like `foo::bar:`, skipping local labels.
+fn asm_label(line: &str) -> Option<&str> {
+    if line.starts_with(|c: char| c.is_whitespace() || c == '.') {
+        return None;
+    }
+    line.strip_suffix(':')
+}
+
+/// The function defined by a line like `define i32 @foo::bar(i32 %x) {`.
+fn llvm_function(line: &str) -> Option<&str> {
+    if !line.starts_with("define ") {
+        return None;
+    }
+    let (_, symbol) = line.split_once('@')?;
+    let symbol = match symbol.strip_prefix('"') {
+        Some(quoted) => quoted.split('"').next()?,
+        None => symbol.split('(').next()?,
+    };
+    Some(symbol)
+}
+
+/// The function defined by a line of MIR or HIR like `fn bar(_1: i32) -> i32 {`.
+fn rust_function(line: &str) -> Option<&str> {
+    let mut rest = line.trim_start();
+    for qualifier in ["pub(crate) ", "pub ", "const ", "async ", "unsafe "] {
+        rest = rest.strip_prefix(qualifier).unwrap_or(rest);
+    }
+    let rest = rest.strip_prefix("fn ")?;
+    rest.split(['(', '<']).next()
+}
+
+/// Whether `line`, ending a function, belongs to it rather than to what follows.
+fn closes(emit: Emit, line: &str) -> bool {
+    match emit {
+        Emit::Asm | Emit::Wasm => line.trim() == "end_function",
+        Emit::LlvmIr | Emit::Mir | Emit::Hir => true,
+    }
+}
+
+/// Whether `line` ends the function started at `start`.
+fn is_end(emit: Emit, start: &str, line: &str) -> bool {
+    match emit {
+        Emit::Asm | Emit::Wasm => {
+            line.starts_with(".Lfunc_end")
+                || line.trim() == "end_function"
+                || asm_label(line).is_some()
+        }
+        Emit::LlvmIr => line == "}",
+        Emit::Mir | Emit::Hir => {
+            let indent = start.len() - start.trim_start().len();
+            line.len() == indent + 1 && line.ends_with('}') && line[..indent].trim().is_empty()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_demangle() {
+        assert_eq!(
+            demangle("\tcallq\t_ZN4play3add17h0123456789abcdefE\n"),
+            "\tcallq\tplay::add\n"
+        );
+        assert_eq!(demangle("_Rust and _ZNothing"), "_Rust and _ZNothing");
+    }
+
+    #[test]
+    fn test_find_functions() {
+        let asm = "play::main:\n\tpushq\t%rax\n.Lfunc_end1:\n\t.size\tmain\nmain:\n\tretq\n";
+        assert_eq!(
+            find_functions(Emit::Asm, asm, "main"),
+            ["play::main:\n\tpushq\t%rax\n"]
+        );
+
+        let hir = "fn add(a: u32, b: u32) -> u32 { a + b }\n\nfn main() {\n    add(1, 2);\n}\n";
+        assert_eq!(
+            find_functions(Emit::Hir, hir, "add"),
+            ["fn add(a: u32, b: u32) -> u32 { a + b }\n"]
+        );
+        assert_eq!(
+            find_functions(Emit::Hir, hir, "main"),
+            ["fn main() {\n    add(1, 2);\n}\n"]
+        );
+
+        let ir = "define internal i32 @play::add(i32 %a) {\nstart:\n  ret i32 %a\n}\n\ndefine i32 @main() {\n}\n";
+        assert_eq!(
+            find_functions(Emit::LlvmIr, ir, "add"),
+            ["define internal i32 @play::add(i32 %a) {\nstart:\n  ret i32 %a\n}\n"]
+        );
+        assert!(find_functions(Emit::LlvmIr, ir, "sub").is_empty());
+    }
+}
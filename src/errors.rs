@@ -1,25 +1,116 @@
+// `failure_derive` generates its impls inside an anonymous const.
+#![allow(non_local_definitions)]
+
 use failure::Fail;
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
+
+/// Exit code for invalid command line arguments.
+pub const EXIT_USAGE: i32 = 64;
+/// Exit code for headers, manifests, configuration or sources that can't be used.
+pub const EXIT_HEADER: i32 = 65;
+/// Exit code for a generated project that fails to build.
+pub const EXIT_BUILD: i32 = 66;
+/// Exit code for unexpected failures of cargo-play itself.
+pub const EXIT_INTERNAL: i32 = 70;
+/// Exit code for failures to read or write files or to start processes.
+pub const EXIT_IO: i32 = 74;
 
 #[derive(Debug, Fail)]
 pub enum CargoPlayError {
-    #[fail(display = "IO error: {:?}", _0)]
-    IOError(std::io::Error),
+    #[fail(display = "IO error")]
+    IOError(#[cause] std::io::Error),
 
-    #[fail(display = "Parsing error: {:?}", _0)]
+    #[fail(display = "Parsing error: {}", _0)]
     ParseError(String),
 
+    #[fail(display = "{}", _0)]
+    HeaderError(HeaderDiagnostic),
+
     #[fail(display = "Unable to compute relative path of {:?}", _0)]
     DiffPathError(std::path::PathBuf),
 
-    #[fail(display = "Unexpected edition {:?}. Edition must be 2015/2018.", _0)]
+    #[fail(
+        display = "Unexpected edition {:?}. Edition must be a year, such as 2021.",
+        _0
+    )]
     InvalidEdition(String),
 
+    #[fail(display = "Edition {} isn't supported by the toolchain: {}", _0, _1)]
+    UnsupportedEdition(String, String),
+
+    #[fail(
+        display = "Unexpected async runtime {:?}. Runtime must be tokio/async-std/smol/builtin.",
+        _0
+    )]
+    InvalidAsyncRuntime(String),
+
+    #[fail(
+        display = "Toolchain {:?} is not installed, run `rustup toolchain install {}`. Installed toolchains: {}.",
+        _0, _0, _1
+    )]
+    ToolchainNotInstalled(String, String),
+
+    #[fail(
+        display = "Invalid matrix axis {:?}. Give each axis once, as toolchain=<toolchains> or edition=<editions> separated by commas.",
+        _0
+    )]
+    InvalidMatrix(String),
+
+    #[fail(
+        display = "{} needs a nightly toolchain, but {:?} is not installed. Run `rustup toolchain install {}` or choose a toolchain with `+toolchain`.",
+        _1, _0, _0
+    )]
+    NightlyNotInstalled(String, String),
+
+    #[fail(
+        display = "Unexpected output {:?}. Output must be asm/llvm-ir/mir/hir/wasm.",
+        _0
+    )]
+    InvalidEmit(String),
+
+    #[fail(
+        display = "No function named {:?} in the output, it may have been inlined or optimized out",
+        _0
+    )]
+    FunctionNotFound(String),
+
+    #[fail(
+        display = "{:?} changed while it was being fixed, the fixes were not written back",
+        _0
+    )]
+    SourceChanged(std::path::PathBuf),
+
+    #[fail(
+        display = "No benchmarks found, add `#[bench]` functions or use criterion's `criterion_group!`"
+    )]
+    NoBenchmarks,
+
+    #[fail(
+        display = "No baseline named {:?}, save one with `--save-baseline {}`",
+        _0, _0
+    )]
+    BaselineNotFound(String),
+
+    #[fail(display = "No scripts matched the given targets")]
+    NoScriptsMatched,
+
+    #[fail(
+        display = "`{}` is declared again with a different requirement at {}:{} (first declared at line {}), keep a single declaration before formatting",
+        _0, _1, _2, _3
+    )]
+    ConflictingHeaders(String, String, usize, usize),
+
+    #[fail(display = "rustfmt failed to format {:?}", _0)]
+    FormatError(std::path::PathBuf),
+
     #[fail(display = "Path already exists at {:?}", _0)]
     PathExistError(std::path::PathBuf),
 
-    #[fail(display = "Failed to parse source code: {:?}", _0)]
-    RustParseError(syn::Error),
+    #[fail(display = "Failed to parse source code")]
+    RustParseError(#[cause] syn::Error),
+
+    #[fail(display = "Invalid configuration in {:?}", _0)]
+    ConfigError(std::path::PathBuf, #[cause] toml::de::Error),
 
     /// Helper error kind only exists for development purpose.
     #[fail(display = "{:?}", _0)]
@@ -46,4 +137,95 @@ impl CargoPlayError {
     pub fn _message<T: Into<String>>(value: T) -> Self {
         CargoPlayError::_Message(value.into())
     }
+
+    /// Exit code reporting this error to the shell.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CargoPlayError::IOError(_) | CargoPlayError::SourceChanged(_) => EXIT_IO,
+            CargoPlayError::DiffPathError(_)
+            | CargoPlayError::UnsupportedEdition(..)
+            | CargoPlayError::ToolchainNotInstalled(..)
+            | CargoPlayError::InvalidMatrix(_)
+            | CargoPlayError::InvalidEmit(_)
+            | CargoPlayError::FunctionNotFound(_)
+            | CargoPlayError::BaselineNotFound(_)
+            | CargoPlayError::NoScriptsMatched
+            | CargoPlayError::NightlyNotInstalled(..)
+            | CargoPlayError::PathExistError(_) => EXIT_USAGE,
+            CargoPlayError::ParseError(_)
+            | CargoPlayError::InvalidEdition(_)
+            | CargoPlayError::InvalidAsyncRuntime(_)
+            | CargoPlayError::HeaderError(_)
+            | CargoPlayError::RustParseError(_)
+            | CargoPlayError::FormatError(_)
+            | CargoPlayError::ConflictingHeaders(..)
+            | CargoPlayError::NoBenchmarks
+            | CargoPlayError::ConfigError(..) => EXIT_HEADER,
+            CargoPlayError::_Message(_) => EXIT_INTERNAL,
+        }
+    }
+
+    /// The error followed by the errors that caused it, one per line.
+    pub fn chain(&self) -> Chain<'_> {
+        Chain(self)
+    }
+}
+
+pub struct Chain<'a>(&'a CargoPlayError);
+
+impl fmt::Display for Chain<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+        for cause in <dyn Fail>::iter_causes(self.0) {
+            write!(f, "\n  caused by: {}", cause)?;
+        }
+        Ok(())
+    }
+}
+
+/// A `//#` header that isn't a valid dependency declaration, rendered like a compiler diagnostic.
+#[derive(Debug)]
+pub struct HeaderDiagnostic {
+    pub message: String,
+    pub file: String,
+    pub line: usize,
+    /// The header line as written
+    pub snippet: String,
+    /// Byte range of `snippet` the diagnostic points at
+    pub span: (usize, usize),
+    /// A corrected header, when one can be guessed
+    pub suggestion: Option<String>,
+}
+
+impl fmt::Display for HeaderDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let number = self.line.to_string();
+        let gutter = " ".repeat(number.len());
+        let (start, end) = self.span;
+        let indent = self.snippet[..start].chars().count();
+        let width = self.snippet[start..end].chars().count().max(1);
+
+        writeln!(f, "invalid header: {}", self.message)?;
+        writeln!(
+            f,
+            "{}--> {}:{}:{}",
+            gutter,
+            self.file,
+            self.line,
+            indent + 1
+        )?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", number, self.snippet)?;
+        write!(
+            f,
+            "{} | {}{}",
+            gutter,
+            " ".repeat(indent),
+            "^".repeat(width)
+        )?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\n{} = help: did you mean `{}`?", gutter, suggestion)?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,278 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::errors::{CargoPlayError, EXIT_BUILD};
+use crate::options::Options;
+use crate::steps::*;
+
+/// Events printed as line-delimited JSON with `--message-format json`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    ProjectPrepared {
+        project: &'a Path,
+        manifest: PathBuf,
+    },
+    /// Cargo's own `compiler-message`, with spans pointing at the original files.
+    CompilerMessage {
+        message: Value,
+    },
+    BuildFinished {
+        success: bool,
+    },
+    ProgramStarted {
+        executable: &'a Path,
+        args: &'a [String],
+    },
+    ProgramOutput {
+        stream: &'a str,
+        line: &'a str,
+    },
+    ProgramExited {
+        code: Option<i32>,
+        signal: Option<i32>,
+        duration: f64,
+    },
+}
+
+/// Receiver of the events of a run.
+pub trait EventSink: Sync {
+    fn emit(&self, event: &Event) -> Result<(), CargoPlayError>;
+
+    /// Called with every process started during the run, so that it can be cancelled.
+    fn started(&self, _child: &Arc<Mutex<Child>>) {}
+}
+
+/// Prints every event as a line of JSON on stdout.
+pub struct Stdout;
+
+impl EventSink for Stdout {
+    fn emit(&self, event: &Event) -> Result<(), CargoPlayError> {
+        write_line(&serde_json::to_value(event).map_err(CargoPlayError::from_serde)?)
+    }
+}
+
+/// Writes `value` as a single line on stdout, so that concurrent writers never interleave.
+pub fn write_line(value: &Value) -> Result<(), CargoPlayError> {
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    writeln!(stdout, "{}", value)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Runs the configured mode on the prepared project and reports its progress as a stream of
+/// events instead of the human readable output. Returns the exit code to exit with.
+pub fn run(
+    options: &Options,
+    temp: &Path,
+    map: &SourceMap,
+    sink: &dyn EventSink,
+) -> Result<i32, CargoPlayError> {
+    let emit = |event: &Event| sink.emit(event);
+    emit(&Event::ProjectPrepared {
+        project: temp,
+        manifest: temp.join("Cargo.toml"),
+    })?;
+
+    let run = options.subcommand() == "run";
+    let mut cargo = if run {
+        cargo_subcommand(options, temp, "build")
+    } else {
+        cargo_subcommand(options, temp, options.subcommand())
+    };
+    cargo.arg("--message-format").arg("json");
+    if !run {
+        cargo.arg("--").args(&options.args);
+    }
+
+    let started = Instant::now();
+    let child = spawn(&mut cargo, sink)?;
+    let stdout = child.lock().unwrap().stdout.take().unwrap();
+    let mut executable = None;
+    let mut built = None;
+    for line in BufReader::new(stdout).lines() {
+        let line = line?;
+        let mut message = match serde_json::from_str::<Value>(&line) {
+            Ok(message) if message.get("reason").is_some() => message,
+            _ => {
+                emit(&Event::ProgramOutput {
+                    stream: "stdout",
+                    line: &line,
+                })?;
+                continue;
+            }
+        };
+
+        match message["reason"].as_str() {
+            Some("compiler-message") => {
+                remap_spans(&mut message["message"], temp, map);
+                emit(&Event::CompilerMessage {
+                    message: message["message"].take(),
+                })?;
+            }
+            Some("compiler-artifact") => {
+                if let Some(path) = message["executable"].as_str() {
+                    executable = Some(PathBuf::from(path));
+                }
+            }
+            Some("build-finished") => {
+                let success = message["success"].as_bool().unwrap_or(false);
+                built = Some(success);
+                emit(&Event::BuildFinished { success })?
+            }
+            _ => {}
+        }
+    }
+    let status = wait(&child)?;
+
+    if !run {
+        if options.subcommand() == "test" {
+            emit_exited(sink, status, started)?;
+        }
+        return Ok(if built == Some(false) {
+            EXIT_BUILD
+        } else {
+            exit_code(status)
+        });
+    }
+
+    let executable = match executable {
+        Some(executable) if status.success() => executable,
+        _ => return Ok(EXIT_BUILD),
+    };
+
+    emit(&Event::ProgramStarted {
+        executable: &executable,
+        args: &options.args,
+    })?;
+    let started = Instant::now();
+    let program = spawn(
+        Command::new(&executable)
+            .args(&options.args)
+            .stderr(Stdio::piped()),
+        sink,
+    )?;
+    let (stdout, stderr) = {
+        let mut program = program.lock().unwrap();
+        (
+            program.stdout.take().unwrap(),
+            program.stderr.take().unwrap(),
+        )
+    };
+
+    std::thread::scope(|scope| {
+        let stderr = scope.spawn(|| forward(sink, "stderr", stderr));
+        forward(sink, "stdout", stdout)?;
+        stderr.join().expect("stderr forwarder panicked")
+    })?;
+
+    let status = wait(&program)?;
+    emit_exited(sink, status, started)?;
+
+    Ok(exit_code(status))
+}
+
+fn spawn(command: &mut Command, sink: &dyn EventSink) -> Result<Arc<Mutex<Child>>, CargoPlayError> {
+    // under `serve` stdin carries the JSON-RPC requests, which must not reach Cargo or the program
+    let child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let child = Arc::new(Mutex::new(child));
+    sink.started(&child);
+    Ok(child)
+}
+
+/// Waits for the child without holding on to its lock, so that it can still be killed meanwhile.
+fn wait(child: &Mutex<Child>) -> Result<ExitStatus, CargoPlayError> {
+    loop {
+        if let Some(status) = child.lock().unwrap().try_wait()? {
+            return Ok(status);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn forward<R: Read>(sink: &dyn EventSink, stream: &str, reader: R) -> Result<(), CargoPlayError> {
+    for line in BufReader::new(reader).lines() {
+        sink.emit(&Event::ProgramOutput {
+            stream,
+            line: &line?,
+        })?;
+    }
+    Ok(())
+}
+
+fn emit_exited(
+    sink: &dyn EventSink,
+    status: ExitStatus,
+    started: Instant,
+) -> Result<(), CargoPlayError> {
+    #[cfg(unix)]
+    let signal = std::os::unix::process::ExitStatusExt::signal(&status);
+    #[cfg(not(unix))]
+    let signal = None;
+
+    sink.emit(&Event::ProgramExited {
+        code: status.code(),
+        signal,
+        duration: started.elapsed().as_secs_f64(),
+    })
+}
+
+/// Rewrites every `file_name` in a diagnostic (including its children and macro expansions), as
+/// well as the rendered text, to refer to the original inputs instead of the generated project.
+fn remap_spans(message: &mut Value, project: &Path, map: &SourceMap) {
+    match message {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                match (key.as_str(), value) {
+                    ("file_name", Value::String(file)) => {
+                        if let Some(original) = map.original(project, Path::new(file.as_str())) {
+                            *file = original.to_string_lossy().into_owned();
+                        }
+                    }
+                    ("rendered", Value::String(rendered)) => {
+                        *rendered = remap_rendered(rendered, project, map);
+                    }
+                    (_, value) => remap_spans(value, project, map),
+                }
+            }
+        }
+        Value::Array(array) => array
+            .iter_mut()
+            .for_each(|value| remap_spans(value, project, map)),
+        _ => {}
+    }
+}
+
+fn remap_rendered(rendered: &str, project: &Path, map: &SourceMap) -> String {
+    rendered
+        .lines()
+        .map(|line| match line.find("--> ") {
+            Some(index) => {
+                let (prefix, location) = line.split_at(index + 4);
+                let file = location.split(':').next().unwrap_or(location);
+                match map.original(project, Path::new(file)) {
+                    Some(original) => format!(
+                        "{}{}{}",
+                        prefix,
+                        original.display(),
+                        &location[file.len()..]
+                    ),
+                    None => line.to_string(),
+                }
+            }
+            None => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        + if rendered.ends_with('\n') { "\n" } else { "" }
+}
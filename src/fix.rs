@@ -0,0 +1,115 @@
+use std::fs;
+use std::path::Path;
+use std::process::Stdio;
+
+use crate::errors::{CargoPlayError, EXIT_BUILD};
+use crate::options::Options;
+use crate::runtime;
+use crate::steps::*;
+
+/// Applies the suggestions of `cargo fix`, or of `cargo clippy --fix` with `--clippy`, to the
+/// generated project, then writes the files they changed back over the user's `files`. Nothing is
+/// written when one of them changed on disk since it was read.
+pub fn fix(
+    options: &Options,
+    project: &Path,
+    map: &SourceMap,
+    files: &[(String, &Path)],
+) -> Result<i32, CargoPlayError> {
+    let mut cargo = cargo_subcommand(options, project, options.subcommand());
+    if options.clippy {
+        cargo.arg("--fix");
+    }
+    // the generated project is neither under version control nor clean
+    cargo.arg("--allow-no-vcs").arg("--allow-dirty");
+    if options.clippy {
+        cargo.arg("--").args(&options.args);
+    }
+    let status = cargo
+        .stderr(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .status()?;
+    if !status.success() {
+        return Ok(EXIT_BUILD);
+    }
+
+    let mut fixed = Vec::new();
+    for (copy, original) in map.files() {
+        let source = files
+            .iter()
+            .find(|(_, path)| Some(*path) == original)
+            .map(|(source, _)| source);
+        if let (Some(original), Some(source)) = (original, source) {
+            let content = restore(source, &fs::read_to_string(project.join(copy))?);
+            if content != *source {
+                fixed.push((original, source, content));
+            }
+        }
+    }
+
+    // check every file first, so that they are either all written back or none of them
+    for (original, source, _) in &fixed {
+        if fs::read_to_string(original)? != **source {
+            return Err(CargoPlayError::SourceChanged(original.to_path_buf()));
+        }
+    }
+
+    if fixed.is_empty() {
+        eprintln!("Nothing to fix");
+    }
+    for (original, _, content) in fixed {
+        fs::write(original, content)?;
+        eprintln!("Fixed {}", original.display());
+    }
+    Ok(0)
+}
+
+/// The fixed copy of `source` as it should be written back: without the entry point added for an
+/// `async fn main`, and with the header block of `source`.
+fn restore(source: &str, fixed: &str) -> String {
+    let mut fixed = remove_shim(fixed);
+    if !source.ends_with('\n') && fixed.ends_with('\n') {
+        fixed.pop();
+    }
+
+    let source: Vec<&str> = source.split_inclusive('\n').collect();
+    let fixed: Vec<&str> = fixed.split_inclusive('\n').collect();
+    let (_, end) = header_block(&source);
+    let (_, fixed_end) = header_block(&fixed);
+    source[..end].concat() + &fixed[fixed_end..].concat()
+}
+
+/// Undoes `runtime::add_shim` on a copy of the script that may have been edited since: drops the
+/// appended entry point and gives `async fn main` its name back.
+fn remove_shim(source: &str) -> String {
+    let source = match source.find(runtime::SHIM_MARKER) {
+        // the shim is separated from the script by a blank line
+        Some(start) => source[..start]
+            .strip_suffix('\n')
+            .unwrap_or(&source[..start]),
+        None => source,
+    };
+    source.replace(runtime::ASYNC_MAIN, "main")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restore() {
+        let source = "#!/usr/bin/env run-cargo-script\n//# tokio = \"1\"\n\nasync fn main() {\n    let mut x = 1;\n    println!(\"{}\", x);\n}";
+        let fixed = format!(
+            "#!/usr/bin/env run-cargo-script\n//# tokio = \"1\"\n\nasync fn {}() {{\n    let x = 1;\n    println!(\"{{}}\", x);\n}}\n\n{}\nfn main() {{\n}}\n",
+            runtime::ASYNC_MAIN,
+            runtime::SHIM_MARKER
+        );
+        assert_eq!(
+            restore(source, &fixed),
+            "#!/usr/bin/env run-cargo-script\n//# tokio = \"1\"\n\nasync fn main() {\n    let x = 1;\n    println!(\"{}\", x);\n}"
+        );
+
+        let source = "//# serde = \"1\"\nfn main() {}\n";
+        assert_eq!(restore(source, "fn main() {}\n"), source);
+    }
+}
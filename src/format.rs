@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use similar::TextDiff;
+use toml::value::{Table, Value};
+
+use crate::cargo::parse_header;
+use crate::errors::CargoPlayError;
+use crate::options::{FmtOptions, RustEdition};
+use crate::steps::*;
+
+/// Formats the code of the files with rustfmt and canonicalizes their header blocks. With
+/// `--check`, prints how the files would change instead and returns 1 when some would.
+pub fn format(options: FmtOptions) -> Result<i32, CargoPlayError> {
+    let files = read_files(&options.src)?;
+    let inputs = input_names(None, &files);
+
+    let mut unformatted = 0;
+    for ((source, path), name) in files.iter().zip(&inputs) {
+        let formatted = format_source(source, path, name)?;
+        if formatted == *source {
+            continue;
+        }
+
+        if options.check {
+            unformatted += 1;
+            print!(
+                "{}",
+                TextDiff::from_lines(source, &formatted)
+                    .unified_diff()
+                    .header(name, name)
+            );
+        } else {
+            std::fs::write(path, formatted)?;
+            eprintln!("Formatted {}", path.display());
+        }
+    }
+
+    if unformatted == 0 {
+        Ok(0)
+    } else {
+        eprintln!("{} file(s) need formatting", unformatted);
+        Ok(1)
+    }
+}
+
+/// The formatted `source` of the file at `path`, whose name for messages is `name`. The header
+/// block is rewritten one dependency per line and kept apart from the code by a blank line.
+fn format_source(source: &str, path: &Path, name: &str) -> Result<String, CargoPlayError> {
+    let edition = edition_directive(source)?.unwrap_or_default();
+    let lines: Vec<&str> = source.split_inclusive('\n').collect();
+    let (position, end) = header_block(&lines);
+    if position == end {
+        return rustfmt(source, path, &edition);
+    }
+
+    let headers = extract_headers(None, &[source]);
+    let mut output = lines[..position].concat();
+    for header in format_headers(&headers, name)? {
+        output.push_str(&format!("//# {}\n", header));
+    }
+
+    let code = rustfmt(&lines[end..].concat(), path, &edition)?;
+    if !code.trim().is_empty() {
+        output.push('\n');
+        output.push_str(&code);
+    }
+    Ok(output)
+}
+
+/// Parses the headers like the manifest does and renders the dependencies they declare sorted by
+/// name, with their `=` aligned. A dependency declared twice the same way is written once, while
+/// one declared again differently is an error: the manifest would only keep the last declaration,
+/// and picking one here would change what gets built.
+fn format_headers(headers: &[Header], file: &str) -> Result<Vec<String>, CargoPlayError> {
+    let inputs = [file.to_string()];
+    let mut dependencies = Table::new();
+    // first declaration of each crate, by normalized name, with its line
+    let mut declared: HashMap<String, (usize, Value)> = HashMap::new();
+    for header in headers {
+        for (name, value) in parse_header(header, &inputs)? {
+            let value = canonical(value);
+            match declared.get(&name.replace('-', "_")) {
+                Some((_, known)) if *known == value => continue,
+                Some((first, _)) => {
+                    return Err(CargoPlayError::ConflictingHeaders(
+                        name,
+                        file.to_string(),
+                        header.line,
+                        *first,
+                    ))
+                }
+                None => {
+                    declared.insert(name.replace('-', "_"), (header.line, value.clone()));
+                    dependencies.insert(name, value);
+                }
+            }
+        }
+    }
+
+    let width = dependencies.keys().map(|name| key(name).len()).max();
+    Ok(dependencies
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "{:<width$} = {}",
+                key(name),
+                inline(value),
+                width = width.unwrap_or_default()
+            )
+        })
+        .collect())
+}
+
+/// A table declaring nothing but a version is written as the bare version.
+fn canonical(value: Value) -> Value {
+    match value {
+        Value::Table(table) if table.len() == 1 && table.contains_key("version") => {
+            table["version"].clone()
+        }
+        value => value,
+    }
+}
+
+/// A TOML key, quoted when it has to be.
+fn key(name: &str) -> String {
+    let bare = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if bare {
+        name.to_string()
+    } else {
+        Value::String(name.into()).to_string()
+    }
+}
+
+/// Renders a value on a single line, with the `version` of tables first.
+fn inline(value: &Value) -> String {
+    match value {
+        Value::Table(table) if table.is_empty() => "{}".into(),
+        Value::Table(table) => {
+            let entries: Vec<String> = table
+                .iter()
+                .filter(|(name, _)| *name == "version")
+                .chain(table.iter().filter(|(name, _)| *name != "version"))
+                .map(|(name, value)| format!("{} = {}", key(name), inline(value)))
+                .collect();
+            format!("{{ {} }}", entries.join(", "))
+        }
+        Value::Array(values) => {
+            let values: Vec<String> = values.iter().map(inline).collect();
+            format!("[{}]", values.join(", "))
+        }
+        value => value.to_string(),
+    }
+}
+
+/// Formats `code` with rustfmt, using the configuration found from the directory of `path`.
+fn rustfmt(code: &str, path: &Path, edition: &RustEdition) -> Result<String, CargoPlayError> {
+    let mut rustfmt = Command::new("rustfmt");
+    if let Some(parent) = path.parent() {
+        rustfmt.current_dir(parent);
+    }
+    let mut child = rustfmt
+        .arg("--edition")
+        .arg(edition.as_str())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()?;
+
+    // rustfmt reads the whole input before writing anything
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(code.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(CargoPlayError::FormatError(path.to_path_buf()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_headers() {
+        let source = "//# serde = { version = \"1\", features = [\"derive\"] }\n//#rand=\"0.8\"\n//# serde = {features=[\"derive\"],version=\"1\"}\n//# log = { version = \"0.4\" }\n//# \"my.crate\" = { path = \"../my\", version = \"*\" }\n";
+        let headers = extract_headers(None, &[source]);
+        assert_eq!(
+            format_headers(&headers, "script.rs").unwrap(),
+            [
+                "log        = \"0.4\"",
+                "\"my.crate\" = { version = \"*\", path = \"../my\" }",
+                "rand       = \"0.8\"",
+                "serde      = { version = \"1\", features = [\"derive\"] }",
+            ]
+        );
+
+        let source = "//# serde = \"1\"\n//# serde = { features = [\"derive\"] }\n";
+        let headers = extract_headers(None, &[source]);
+        assert_eq!(
+            format_headers(&headers, "script.rs")
+                .unwrap_err()
+                .to_string(),
+            "`serde` is declared again with a different requirement at script.rs:2 (first declared \
+             at line 1), keep a single declaration before formatting"
+        );
+    }
+}
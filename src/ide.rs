@@ -0,0 +1,54 @@
+use std::path::{Path, PathBuf};
+
+use log::debug;
+
+use crate::errors::CargoPlayError;
+use crate::options::Options;
+use crate::steps::*;
+use crate::watch;
+
+/// Generates a Cargo project for rust-analyzer outside the cached project, at a path that only
+/// depends on the input files. Its binary target points straight at the original entry file, so
+/// the editor resolves modules and dependencies declared in the `//#` headers while editing the
+/// script itself. With `--watch` the project is regenerated whenever the headers change.
+pub fn setup(options: &Options) -> Result<i32, CargoPlayError> {
+    let project = temp_dir(options.ide_dirname());
+    let manifest = write_project(options, &project)?;
+
+    println!("Linked project: {}", manifest.display());
+    println!("Point rust-analyzer at it, e.g. in the VS Code settings:");
+    println!(
+        "    \"rust-analyzer.linkedProjects\": [{}]",
+        serde_json::Value::from(manifest.to_string_lossy())
+    );
+
+    if !options.watch {
+        return Ok(0);
+    }
+
+    loop {
+        watch::wait_for_change(&options.src, watch::snapshot(&options.src), || Ok(()))?;
+        match write_project(options, &project) {
+            Ok(_) => debug!("Regenerated {:?}", manifest),
+            Err(err) => eprintln!("error: {}", err.chain()),
+        }
+    }
+}
+
+fn write_project(options: &Options, project: &Path) -> Result<PathBuf, CargoPlayError> {
+    let files = read_files(&options.src)?;
+    let sources: Vec<&str> = files.iter().map(|(source, _)| -> &str { source }).collect();
+
+    let analysis = analyze_dependencies(options, None, &sources)?;
+    let infers = resolve_dependencies(options, &temp_dir(options.temp_dirname()), analysis)?;
+
+    write_ide_manifest(
+        project,
+        options.package_name(),
+        &extract_headers(None, &sources),
+        &input_names(None, &files),
+        edition(options, sources.first().copied())?,
+        infers,
+        options.src[0].clone(),
+    )
+}
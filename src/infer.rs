@@ -1,55 +1,985 @@
-use std::collections::HashSet;
-use std::iter;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
 
-use proc_macro2::{Ident, TokenStream, TokenTree};
+use proc_macro2::{Ident, Spacing, TokenStream, TokenTree};
 use quote::ToTokens;
+use semver::Version;
 
+use crate::config::Config;
 use crate::errors::CargoPlayError;
+use crate::registry::Registry;
 
-const USE_KEYWORDS: &'static [&'static str] = &["std", "core", "crate", "self", "alloc", "super"];
+const USE_KEYWORDS: &[&str] = &["std", "core", "crate", "self", "alloc", "super"];
 
-fn extra_use<'a, T: 'a + IntoIterator<Item = TokenTree> + Clone>(
-    input: T,
-) -> Box<dyn Iterator<Item = Ident> + 'a> {
-    use proc_macro2::Literal;
+/// Names that can start a path without referring to an external crate: primitive types, prelude
+/// items and the crates shipped with the toolchain.
+const BUILTIN_NAMES: &[&str] = &[
+    "Self",
+    "bool",
+    "char",
+    "str",
+    "i8",
+    "i16",
+    "i32",
+    "i64",
+    "i128",
+    "isize",
+    "u8",
+    "u16",
+    "u32",
+    "u64",
+    "u128",
+    "usize",
+    "f32",
+    "f64",
+    "Box",
+    "Option",
+    "Some",
+    "None",
+    "Result",
+    "Ok",
+    "Err",
+    "String",
+    "ToString",
+    "ToOwned",
+    "Vec",
+    "Default",
+    "Iterator",
+    "IntoIterator",
+    "From",
+    "Into",
+    "TryFrom",
+    "TryInto",
+    "proc_macro",
+    "test",
+];
+
+/// Keywords that may directly precede a path starting with `::` (edition 2015).
+const PATH_PRECEDING_KEYWORDS: &[&str] = &[
+    "return", "in", "as", "mut", "else", "match", "if", "while", "break", "let", "const", "static",
+];
+
+/// Packages whose name differs from the name of their library, for when the local registry index
+/// can't tell.
+const KNOWN_PACKAGES: &[(&str, &str)] = &[
+    ("actix_web", "actix-web"),
+    ("async_std", "async-std"),
+    ("async_trait", "async-trait"),
+    ("color_eyre", "color-eyre"),
+    ("crossbeam_channel", "crossbeam-channel"),
+    ("crossbeam_utils", "crossbeam-utils"),
+    ("futures_util", "futures-util"),
+    ("js_sys", "js-sys"),
+    ("md5", "md-5"),
+    ("num_bigint", "num-bigint"),
+    ("num_integer", "num-integer"),
+    ("num_traits", "num-traits"),
+    ("proc_macro2", "proc-macro2"),
+    ("quick_xml", "quick-xml"),
+    ("regex_syntax", "regex-syntax"),
+    ("tokio_stream", "tokio-stream"),
+    ("tokio_util", "tokio-util"),
+    ("tracing_subscriber", "tracing-subscriber"),
+    ("unicode_segmentation", "unicode-segmentation"),
+    ("unicode_width", "unicode-width"),
+    ("wasm_bindgen", "wasm-bindgen"),
+    ("web_sys", "web-sys"),
+    ("xml", "xml-rs"),
+];
+
+/// Kinds of usage that require a crate feature.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Usage {
+    /// A derive macro, by name or full path.
+    Derive,
+    /// An attribute macro, by full path.
+    Attribute,
+    /// A path, or any path below it.
+    Path,
+}
+
+/// Features needed by usages of the crates, by the name the sources use for the crate.
+const FEATURE_RULES: &[(&str, Usage, &str, &[&str])] = &[
+    (
+        "async_std",
+        Usage::Attribute,
+        "async_std::main",
+        &["attributes"],
+    ),
+    (
+        "async_std",
+        Usage::Attribute,
+        "async_std::test",
+        &["attributes"],
+    ),
+    ("clap", Usage::Derive, "Args", &["derive"]),
+    ("clap", Usage::Derive, "Parser", &["derive"]),
+    ("clap", Usage::Derive, "Subcommand", &["derive"]),
+    ("clap", Usage::Derive, "ValueEnum", &["derive"]),
+    ("reqwest", Usage::Path, "reqwest::blocking", &["blocking"]),
+    ("serde", Usage::Derive, "Deserialize", &["derive"]),
+    ("serde", Usage::Derive, "Serialize", &["derive"]),
+    (
+        "tokio",
+        Usage::Attribute,
+        "tokio::main",
+        &["macros", "rt-multi-thread"],
+    ),
+    ("tokio", Usage::Attribute, "tokio::test", &["macros", "rt"]),
+    ("tokio", Usage::Path, "tokio::fs", &["fs"]),
+    (
+        "tokio",
+        Usage::Path,
+        "tokio::io::AsyncBufReadExt",
+        &["io-util"],
+    ),
+    (
+        "tokio",
+        Usage::Path,
+        "tokio::io::AsyncReadExt",
+        &["io-util"],
+    ),
+    (
+        "tokio",
+        Usage::Path,
+        "tokio::io::AsyncWriteExt",
+        &["io-util"],
+    ),
+    ("tokio", Usage::Path, "tokio::io::stderr", &["io-std"]),
+    ("tokio", Usage::Path, "tokio::io::stdin", &["io-std"]),
+    ("tokio", Usage::Path, "tokio::io::stdout", &["io-std"]),
+    ("tokio", Usage::Path, "tokio::join", &["macros"]),
+    ("tokio", Usage::Path, "tokio::net", &["net"]),
+    ("tokio", Usage::Path, "tokio::process", &["process"]),
+    ("tokio", Usage::Path, "tokio::runtime", &["rt-multi-thread"]),
+    ("tokio", Usage::Path, "tokio::select", &["macros"]),
+    ("tokio", Usage::Path, "tokio::signal", &["signal"]),
+    ("tokio", Usage::Path, "tokio::spawn", &["rt"]),
+    ("tokio", Usage::Path, "tokio::sync", &["sync"]),
+    ("tokio", Usage::Path, "tokio::task", &["rt"]),
+    ("tokio", Usage::Path, "tokio::time", &["time"]),
+    ("uuid", Usage::Path, "uuid::Uuid::new_v4", &["v4"]),
+];
+
+/// How a crate candidate was found. Candidates from `use` and `extern crate` are trusted as they
+/// are, while the first segments of other paths are most often types.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Origin {
+    Use,
+    Path,
+}
+
+fn is_punct(token: Option<&TokenTree>, ch: char) -> bool {
+    matches!(token, Some(TokenTree::Punct(punct)) if punct.as_char() == ch)
+}
+
+fn is_ident(token: Option<&TokenTree>, name: &str) -> bool {
+    matches!(token, Some(TokenTree::Ident(ident)) if ident == name)
+}
+
+/// Whether a `::` starts at `index`.
+fn is_path_separator(tokens: &[TokenTree], index: usize) -> bool {
+    let joint = matches!(
+        tokens.get(index),
+        Some(TokenTree::Punct(punct)) if punct.as_char() == ':' && punct.spacing() == Spacing::Joint
+    );
+    joint && is_punct(tokens.get(index + 1), ':')
+}
+
+/// Collects the names that may refer to an external crate: the identifier after `use` or
+/// `extern crate`, and the first segment of every other path (`serde_json::from_str`,
+/// `lazy_static::lazy_static!`, `::regex::Regex`, `#[tokio::main]`), including the ones inside
+/// macro invocations.
+fn extra_crates(input: TokenStream, candidates: &mut Vec<(Ident, Origin)>) {
     use TokenTree as tt;
 
-    Box::new(
-        input
-            .clone()
-            .into_iter()
-            .zip(
-                input
-                    .into_iter()
-                    .skip(1)
-                    .chain(iter::once(Literal::u8_suffixed(1u8).into())),
-            )
-            .flat_map(|(prev, current)| match (prev, current) {
-                (tt::Ident(ref first), tt::Ident(ref second)) if first.to_string() == "use" => {
-                    Box::new(iter::once(second.clone()))
+    let tokens: Vec<TokenTree> = input.into_iter().collect();
+    for (index, token) in tokens.iter().enumerate() {
+        let ident = match token {
+            tt::Group(group) => {
+                extra_crates(group.stream(), candidates);
+                continue;
+            }
+            tt::Ident(ident) => ident,
+            _ => continue,
+        };
+
+        let prev = index.checked_sub(1).and_then(|i| tokens.get(i));
+        let prev2 = index.checked_sub(2).and_then(|i| tokens.get(i));
+
+        if is_ident(prev, "use") || (is_ident(prev, "crate") && is_ident(prev2, "extern")) {
+            candidates.push((ident.clone(), Origin::Use));
+            continue;
+        }
+
+        // a path root is followed by `::`, but never by generic arguments
+        if !is_path_separator(&tokens, index + 1) || is_punct(tokens.get(index + 3), '<') {
+            continue;
+        }
+
+        // method calls (`x.parse::<T>()`) and macro variables (`$x::`)
+        if is_punct(prev, '.') || is_punct(prev, '$') {
+            continue;
+        }
+
+        if index >= 2 && is_path_separator(&tokens, index - 2) {
+            // only a leading `::` makes this a root, as opposed to `a::b` or `<T as X>::b`
+            let before = index.checked_sub(3).and_then(|i| tokens.get(i));
+            let continues_path = match before {
+                Some(tt::Ident(ident)) => {
+                    !PATH_PRECEDING_KEYWORDS.contains(&ident.to_string().as_str())
                 }
-                (tt::Group(ref group), _) => extra_use(group.stream()),
-                _ => Box::new(iter::empty()),
+                Some(tt::Punct(punct)) => punct.as_char() == '>',
+                _ => false,
+            };
+            if continues_path {
+                continue;
+            }
+        }
+
+        candidates.push((ident.clone(), Origin::Path));
+    }
+}
+
+/// Collects the names the sources define themselves: modules, types, traits, macros and the
+/// names brought into scope by `use`, at any nesting level.
+#[derive(Default)]
+struct LocalNames(HashSet<String>);
+
+impl LocalNames {
+    /// `root` is the first segment of the `use` path and `parent` the last one above `tree`, when
+    /// it isn't the root.
+    fn add_use_tree(&mut self, root: Option<&Ident>, parent: Option<&Ident>, tree: &syn::UseTree) {
+        use syn::UseTree;
+
+        let at_root = root.is_none();
+        let root = match (root, tree) {
+            (Some(root), _) => root,
+            (None, UseTree::Path(path)) => &path.ident,
+            (None, UseTree::Name(name)) => &name.ident,
+            (None, UseTree::Rename(rename)) => &rename.ident,
+            _ => return,
+        };
+
+        match tree {
+            UseTree::Path(path) => {
+                let parent = if at_root { None } else { Some(&path.ident) };
+                self.add_use_tree(Some(root), parent, &path.tree)
+            }
+            UseTree::Group(group) => group
+                .items
+                .iter()
+                .for_each(|item| self.add_use_tree(Some(root), parent, item)),
+            // `use std::io::{self};` names `io`, while `use rand::{self};` only names the crate
+            UseTree::Name(name) if name.ident == "self" => {
+                if let Some(parent) = parent {
+                    self.0.insert(parent.to_string());
+                }
+            }
+            // `use rand;` only names the crate itself
+            UseTree::Name(name) if name.ident != *root => {
+                self.0.insert(name.ident.to_string());
+            }
+            UseTree::Rename(rename) if rename.rename != *root => {
+                self.0.insert(rename.rename.to_string());
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'ast> syn::visit::Visit<'ast> for LocalNames {
+    fn visit_item_mod(&mut self, item: &'ast syn::ItemMod) {
+        self.0.insert(item.ident.to_string());
+        syn::visit::visit_item_mod(self, item);
+    }
+
+    fn visit_item_struct(&mut self, item: &'ast syn::ItemStruct) {
+        self.0.insert(item.ident.to_string());
+    }
+
+    fn visit_item_enum(&mut self, item: &'ast syn::ItemEnum) {
+        self.0.insert(item.ident.to_string());
+    }
+
+    fn visit_item_union(&mut self, item: &'ast syn::ItemUnion) {
+        self.0.insert(item.ident.to_string());
+    }
+
+    fn visit_item_trait(&mut self, item: &'ast syn::ItemTrait) {
+        self.0.insert(item.ident.to_string());
+        syn::visit::visit_item_trait(self, item);
+    }
+
+    fn visit_item_type(&mut self, item: &'ast syn::ItemType) {
+        self.0.insert(item.ident.to_string());
+    }
+
+    fn visit_item_macro(&mut self, item: &'ast syn::ItemMacro) {
+        if let Some(ident) = &item.ident {
+            self.0.insert(ident.to_string());
+        }
+    }
+
+    fn visit_item_extern_crate(&mut self, item: &'ast syn::ItemExternCrate) {
+        if let Some((_, rename)) = &item.rename {
+            self.0.insert(rename.to_string());
+        }
+    }
+
+    fn visit_item_use(&mut self, item: &'ast syn::ItemUse) {
+        self.add_use_tree(None, None, &item.tree);
+    }
+}
+
+fn path_to_string(path: &syn::Path) -> String {
+    path.segments
+        .iter()
+        .map(|segment| segment.ident.to_string())
+        .collect::<Vec<_>>()
+        .join("::")
+}
+
+/// Collects the derives, attributes and paths used in the sources, to match them against the
+/// feature rules.
+#[derive(Debug, Default)]
+pub struct Usages {
+    derives: HashSet<String>,
+    attributes: HashSet<String>,
+    paths: HashSet<String>,
+}
+
+impl Usages {
+    fn add_use_tree(&mut self, prefix: &str, tree: &syn::UseTree) {
+        use syn::UseTree;
+
+        let join = |ident: &Ident| {
+            if prefix.is_empty() {
+                ident.to_string()
+            } else {
+                format!("{}::{}", prefix, ident)
+            }
+        };
+
+        match tree {
+            UseTree::Path(path) => self.add_use_tree(&join(&path.ident), &path.tree),
+            UseTree::Name(name) => {
+                self.paths.insert(join(&name.ident));
+            }
+            UseTree::Rename(rename) => {
+                self.paths.insert(join(&rename.ident));
+            }
+            UseTree::Glob(_) => {
+                self.paths.insert(prefix.into());
+            }
+            UseTree::Group(group) => group
+                .items
+                .iter()
+                .for_each(|item| self.add_use_tree(prefix, item)),
+        }
+    }
+
+    fn matches(&self, usage: Usage, pattern: &str) -> bool {
+        match usage {
+            Usage::Derive => self
+                .derives
+                .iter()
+                .any(|derive| derive == pattern || derive.rsplit("::").next() == Some(pattern)),
+            Usage::Attribute => self.attributes.contains(pattern),
+            Usage::Path => self.paths.iter().any(|path| {
+                path == pattern
+                    || (path.starts_with(pattern) && path[pattern.len()..].starts_with("::"))
             }),
-    )
+        }
+    }
 }
 
-pub fn analyze_sources(
-    stdin: Option<&str>,
-    sources: &[&str],
-) -> Result<HashSet<String>, CargoPlayError> {
-    let streams: Vec<TokenStream> = stdin
+impl<'ast> syn::visit::Visit<'ast> for Usages {
+    fn visit_attribute(&mut self, attr: &'ast syn::Attribute) {
+        if !attr.path.is_ident("derive") {
+            self.attributes.insert(path_to_string(&attr.path));
+            return;
+        }
+
+        if let Ok(syn::Meta::List(list)) = attr.parse_meta() {
+            for nested in list.nested {
+                if let syn::NestedMeta::Meta(meta) = nested {
+                    self.derives.insert(path_to_string(meta.path()));
+                }
+            }
+        }
+    }
+
+    fn visit_path(&mut self, path: &'ast syn::Path) {
+        self.paths.insert(path_to_string(path));
+        syn::visit::visit_path(self, path);
+    }
+
+    fn visit_item_use(&mut self, item: &'ast syn::ItemUse) {
+        self.add_use_tree("", &item.tree);
+    }
+}
+
+/// An `async fn main` with no attribute (such as `#[tokio::main]`) turning it into a regular
+/// entry point.
+#[derive(Debug, PartialEq)]
+pub struct AsyncMain {
+    /// Line (1-based) and column (in characters) of the `main` identifier
+    pub line: usize,
+    pub column: usize,
+    /// The return type, e.g. `-> std::io::Result<()>`
+    pub output: String,
+}
+
+pub fn async_main(source: &str) -> Result<Option<AsyncMain>, CargoPlayError> {
+    let file = syn::parse_file(source)?;
+
+    Ok(file.items.iter().find_map(|item| match item {
+        syn::Item::Fn(function)
+            if function.sig.ident == "main"
+                && function.sig.asyncness.is_some()
+                && !function.attrs.iter().any(|attr| {
+                    attr.path
+                        .segments
+                        .last()
+                        .is_some_and(|segment| segment.ident == "main")
+                }) =>
+        {
+            let start = function.sig.ident.span().start();
+            Some(AsyncMain {
+                line: start.line,
+                column: start.column,
+                output: function.sig.output.to_token_stream().to_string(),
+            })
+        }
+        _ => None,
+    }))
+}
+
+/// Unstable features enabled by the crate-level `#![feature(...)]` attributes of an entry point.
+pub fn unstable_features(source: &str) -> Result<Vec<String>, CargoPlayError> {
+    let file = syn::parse_file(source)?;
+
+    Ok(file
+        .attrs
+        .iter()
+        .filter(|attr| {
+            matches!(attr.style, syn::AttrStyle::Inner(_)) && attr.path.is_ident("feature")
+        })
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(syn::Meta::List(list)) => Some(list.nested),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|nested| match nested {
+            syn::NestedMeta::Meta(meta) => meta.path().get_ident().map(ToString::to_string),
+            _ => None,
+        })
+        .collect())
+}
+
+/// The harness running the benchmarks of a script.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BenchHarness {
+    /// The unstable `#[bench]` functions of libtest
+    Libtest,
+    /// Criterion, whose `criterion_main!` provides the entry point
+    Criterion,
+}
+
+#[derive(Default)]
+struct Benches {
+    libtest: bool,
+    criterion: bool,
+}
+
+impl<'ast> syn::visit::Visit<'ast> for Benches {
+    fn visit_attribute(&mut self, attr: &'ast syn::Attribute) {
+        self.libtest |= attr.path.is_ident("bench");
+    }
+
+    fn visit_macro(&mut self, mac: &'ast syn::Macro) {
+        self.criterion |= mac.path.segments.last().is_some_and(|segment| {
+            segment.ident == "criterion_group" || segment.ident == "criterion_main"
+        });
+        syn::visit::visit_macro(self, mac);
+    }
+}
+
+/// The harness the benchmarks of an entry point need, when it has some: criterion when it uses
+/// `criterion_group!` or `criterion_main!`, else libtest when it has `#[bench]` functions.
+pub fn bench_harness(source: &str) -> Result<Option<BenchHarness>, CargoPlayError> {
+    let file = syn::parse_file(source)?;
+    let mut benches = Benches::default();
+    syn::visit::visit_file(&mut benches, &file);
+
+    Ok(if benches.criterion {
+        Some(BenchHarness::Criterion)
+    } else if benches.libtest {
+        Some(BenchHarness::Libtest)
+    } else {
+        None
+    })
+}
+
+/// Where a crate is used: the index of the source (stdin first, then the files) and the line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    pub source: usize,
+    pub line: usize,
+}
+
+/// Crates used by the sources but not defined by them, with how they are used.
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub crates: HashSet<String>,
+    pub usages: Usages,
+    /// First use of each crate, preferring `use` and `extern crate` items
+    locations: HashMap<String, (Location, Origin)>,
+    /// Features needed by the code cargo-play generates, by crate
+    pub required: HashMap<String, BTreeSet<String>>,
+}
+
+impl Analysis {
+    /// Adds a crate needed by generated code, even when it isn't used by the sources.
+    pub fn require(&mut self, name: &str, features: &[&str]) {
+        self.crates.insert(name.into());
+        self.required
+            .entry(name.into())
+            .or_default()
+            .extend(features.iter().map(|feature| feature.to_string()));
+    }
+}
+
+pub fn analyze_sources(stdin: Option<&str>, sources: &[&str]) -> Result<Analysis, CargoPlayError> {
+    let files: Vec<syn::File> = stdin
         .iter()
         .chain(sources.iter())
-        .map(|source| -> Result<_, CargoPlayError> {
-            Ok(syn::parse_file(source)?.into_token_stream())
+        .map(|source| syn::parse_file(source))
+        .collect::<Result<_, _>>()?;
+
+    let mut locals = LocalNames::default();
+    let mut usages = Usages::default();
+    let mut candidates = Vec::new();
+    for (source, file) in files.into_iter().enumerate() {
+        syn::visit::visit_file(&mut locals, &file);
+        syn::visit::visit_file(&mut usages, &file);
+
+        let mut found = Vec::new();
+        extra_crates(file.into_token_stream(), &mut found);
+        candidates.extend(found.into_iter().map(|(ident, origin)| {
+            let line = ident.span().start().line;
+            (ident.to_string(), origin, Location { source, line })
+        }));
+    }
+
+    let mut analysis = Analysis {
+        usages,
+        ..Default::default()
+    };
+    for (name, origin, location) in candidates {
+        if (origin == Origin::Path && name.starts_with(|c: char| c.is_ascii_uppercase()))
+            || USE_KEYWORDS.contains(&name.as_ref())
+            || BUILTIN_NAMES.contains(&name.as_ref())
+            || locals.0.contains(&name)
+        {
+            continue;
+        }
+
+        // point at a `use` rather than any other path when there is one
+        let known = analysis.locations.get(&name).map(|(_, origin)| *origin);
+        if known.is_none() || (known == Some(Origin::Path) && origin == Origin::Use) {
+            analysis.locations.insert(name.clone(), (location, origin));
+        }
+        analysis.crates.insert(name);
+    }
+
+    Ok(analysis)
+}
+
+/// Finds the package providing the crate the sources refer to as `name`: the user's mapping comes
+/// first, then the built-in table, then the packages known to the local registry index.
+pub fn package_name(name: &str, config: &Config, registry: &Registry) -> String {
+    if let Some(package) = config.crates.get(name) {
+        return package.clone();
+    }
+
+    if let Some((_, package)) = KNOWN_PACKAGES.iter().find(|(known, _)| *known == name) {
+        return (*package).into();
+    }
+
+    let dashed = name.replace('_', "-");
+    if dashed != name && !registry.contains(name) && registry.contains(&dashed) {
+        return dashed;
+    }
+
+    name.into()
+}
+
+/// Where the version of an inferred crate was found.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VersionSource {
+    Lockfile,
+    Registry,
+}
+
+impl fmt::Display for VersionSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VersionSource::Lockfile => write!(f, "Cargo.lock"),
+            VersionSource::Registry => write!(f, "registry index cache"),
+        }
+    }
+}
+
+/// A dependency inferred from the sources.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InferredCrate {
+    pub package: String,
+    /// Unknown when the crate is neither locked nor in the local index.
+    pub version: Option<(Version, VersionSource)>,
+    pub features: BTreeSet<String>,
+    /// Where the sources use the crate, unknown for crates needed by generated code
+    pub location: Option<Location>,
+}
+
+impl InferredCrate {
+    /// Version requirement to write in the manifest.
+    pub fn requirement(&self) -> String {
+        match &self.version {
+            Some((version, _)) => format!("^{}", version),
+            None => "*".into(),
+        }
+    }
+}
+
+/// Features of the crate used as `name` required by the usages, from the built-in rules and the
+/// user's.
+fn features(name: &str, usages: &Usages, config: &Config) -> BTreeSet<String> {
+    let builtin = FEATURE_RULES
+        .iter()
+        .filter(|(krate, usage, pattern, _)| *krate == name && usages.matches(*usage, pattern))
+        .flat_map(|(_, _, _, features)| features.iter().map(|feature| feature.to_string()));
+
+    let user = config
+        .features
+        .iter()
+        .filter(|rule| rule.name == name)
+        .filter(|rule| {
+            let patterns = [
+                (Usage::Derive, &rule.derive),
+                (Usage::Attribute, &rule.attribute),
+                (Usage::Path, &rule.path),
+            ];
+            patterns.iter().any(|(usage, pattern)| {
+                pattern
+                    .as_ref()
+                    .is_some_and(|pattern| usages.matches(*usage, pattern))
+            })
         })
-        .collect::<Result<_, CargoPlayError>>()?;
+        .flat_map(|rule| rule.features.iter().cloned());
+
+    builtin.chain(user).collect()
+}
 
-    Ok(streams
+/// Maps each inferred name to the package providing it, at the version already in `locked`, or
+/// else the newest one in the local registry index, with the features its usages require.
+pub fn resolve_crates(
+    analysis: Analysis,
+    config: &Config,
+    registry: &Registry,
+    locked: &HashMap<String, Version>,
+) -> HashMap<String, InferredCrate> {
+    let Analysis {
+        crates,
+        usages,
+        locations,
+        required,
+    } = analysis;
+    crates
         .into_iter()
-        .flat_map(|token| extra_use(token.into_iter()))
-        .map(|ident| ident.to_string())
-        .filter(|ident| !USE_KEYWORDS.contains(&ident.as_ref()))
-        .collect())
+        .map(|name| {
+            let package = package_name(&name, config, registry);
+            let version = match locked.get(&package) {
+                Some(version) => Some((version.clone(), VersionSource::Lockfile)),
+                None => registry
+                    .newest(&package)
+                    .map(|version| (version, VersionSource::Registry)),
+            };
+            let mut features = features(&name, &usages, config);
+            features.extend(required.get(&name).into_iter().flatten().cloned());
+            let location = locations.get(&name).map(|(location, _)| *location);
+            (
+                name,
+                InferredCrate {
+                    package,
+                    version,
+                    features,
+                    location,
+                },
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FeatureRule;
+
+    fn infer(source: &str) -> Vec<String> {
+        let mut crates: Vec<String> = analyze_sources(None, &[source])
+            .unwrap()
+            .crates
+            .into_iter()
+            .collect();
+        crates.sort();
+        crates
+    }
+
+    #[test]
+    fn test_use_and_extern_crate() {
+        assert_eq!(
+            infer("extern crate rand; use regex::Regex; use std::io; fn main() {}"),
+            ["rand", "regex"]
+        );
+    }
+
+    #[test]
+    fn test_paths_and_macros() {
+        let source = r#"
+            lazy_static::lazy_static! { static ref X: u8 = 1; }
+            #[tokio::main]
+            async fn main() {
+                let v: serde_json::Value = serde_json::from_str("1").unwrap();
+                println!("{}", itertools::join(&[1, 2], ","));
+                let n = ::regex::Regex::new("a");
+            }
+        "#;
+        assert_eq!(
+            infer(source),
+            ["itertools", "lazy_static", "regex", "serde_json", "tokio"]
+        );
+    }
+
+    #[test]
+    fn test_ignores_non_crate_paths() {
+        let source = r#"
+            struct Foo;
+            impl Foo { fn new() -> Self { Self::helper(); Foo } fn helper() {} }
+            fn main() {
+                let _ = Foo::new();
+                let _ = i32::MAX;
+                let _ = String::new();
+                let _ = "1".parse::<u8>();
+                let _ = Vec::<u8>::new();
+                let _ = <Foo as Default>::default();
+                let _: Vec<u8> = std::iter::empty().collect::<Vec<_>>();
+            }
+            macro_rules! m { ($x:ident) => { $x::call() }; }
+        "#;
+        assert!(infer(source).is_empty());
+    }
+
+    #[test]
+    fn test_ignores_local_items() {
+        let source = r#"
+            mod utils;
+            mod inline { pub fn f() {} }
+            use utils::helper;
+            use serde_json as json;
+            use std::collections::hash_map;
+            enum MyEnum { A }
+            fn main() {
+                use MyEnum::*;
+                inline::f();
+                hash_map::HashMap::<u8, u8>::new();
+                json::from_str::<u8>("1");
+            }
+        "#;
+        assert_eq!(infer(source), ["serde_json"]);
+    }
+
+    #[test]
+    fn test_ignores_nested_self_imports() {
+        let source = r#"
+            use std::io::{self, Read};
+            use std::{fmt::{self, Display}};
+            use rand::{self};
+            fn main() {
+                let mut input = String::new();
+                io::stdin().read_to_string(&mut input).unwrap();
+                fmt::format(format_args!("{}", input));
+                rand::random::<u8>();
+            }
+        "#;
+        assert_eq!(infer(source), ["rand"]);
+    }
+
+    #[test]
+    fn test_ignores_modules_across_files() {
+        let entry = "mod hello; fn main() { hello::world(); rand::random::<u8>(); }";
+        let hello = "use rand; pub fn world() {}";
+        let crates = analyze_sources(None, &[entry, hello]).unwrap().crates;
+        assert_eq!(crates.into_iter().collect::<Vec<_>>(), ["rand"]);
+    }
+
+    #[test]
+    fn test_package_name() {
+        let index = std::env::temp_dir().join("cargo-play.test-registry");
+        for entry in ["pr/et/pretty-env", "pr/et/pretty_log", "3/f/foo"] {
+            let path = index.join(".cache").join(entry);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        let registry = Registry::new(vec![index]);
+
+        let mut config = Config::default();
+        config.crates.insert("foo".into(), "foo-bar".into());
+
+        assert_eq!(
+            package_name("regex_syntax", &config, &registry),
+            "regex-syntax"
+        );
+        assert_eq!(package_name("pretty_env", &config, &registry), "pretty-env");
+        assert_eq!(package_name("pretty_log", &config, &registry), "pretty_log");
+        assert_eq!(package_name("foo", &config, &registry), "foo-bar");
+        assert_eq!(
+            package_name("unknown_crate", &config, &registry),
+            "unknown_crate"
+        );
+    }
+
+    #[test]
+    fn test_resolve_versions() {
+        let index = std::env::temp_dir().join("cargo-play.test-versions");
+        let mut cache = vec![3, 2, 0, 0, 0];
+        cache.extend_from_slice(b"etag\0");
+        for (version, yanked) in [("1.2.0", false), ("1.3.0", true), ("2.0.0-rc.1", false)] {
+            let entry = format!(
+                r#"{{"name":"rand-x","vers":"{}","yanked":{}}}"#,
+                version, yanked
+            );
+            cache.extend_from_slice(format!("{}\0{}\0", version, entry).as_bytes());
+        }
+        let path = index.join(".cache/ra/nd/rand-x");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, cache).unwrap();
+        let registry = Registry::new(vec![index]);
+
+        let names: HashSet<String> = ["rand_x", "other"].iter().map(|s| s.to_string()).collect();
+        let analysis = || Analysis {
+            crates: names.clone(),
+            ..Default::default()
+        };
+        let config = Config::default();
+
+        let crates = resolve_crates(analysis(), &config, &registry, &HashMap::new());
+        assert_eq!(crates["rand_x"].package, "rand-x");
+        assert_eq!(crates["rand_x"].requirement(), "^1.2.0");
+        assert_eq!(crates["other"].requirement(), "*");
+
+        let mut locked = HashMap::new();
+        locked.insert("rand-x".to_string(), Version::new(1, 1, 5));
+        let crates = resolve_crates(analysis(), &config, &registry, &locked);
+        assert_eq!(
+            crates["rand_x"].version,
+            Some((Version::new(1, 1, 5), VersionSource::Lockfile))
+        );
+    }
+
+    #[test]
+    fn test_features() {
+        let source = r#"
+            use serde::Serialize;
+            use tokio::io::AsyncReadExt;
+            #[derive(Debug, Serialize)]
+            struct Point { x: i32 }
+            #[tokio::main]
+            async fn main() {
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                my_crate::special::call();
+            }
+        "#;
+        let analysis = analyze_sources(None, &[source]).unwrap();
+
+        let mut config = Config::default();
+        config.features.push(FeatureRule {
+            name: "my_crate".into(),
+            path: Some("my_crate::special".into()),
+            features: vec!["special".into()],
+            ..Default::default()
+        });
+
+        let crates = resolve_crates(analysis, &config, &Registry::default(), &HashMap::new());
+        let features = |name: &str| crates[name].features.iter().cloned().collect::<Vec<_>>();
+        assert_eq!(features("serde"), ["derive"]);
+        assert_eq!(
+            features("tokio"),
+            ["io-util", "macros", "rt-multi-thread", "time"]
+        );
+        assert_eq!(features("my_crate"), ["special"]);
+    }
+
+    #[test]
+    fn test_locations() {
+        let entry = "fn main() {\n    rand::random::<u8>();\n}\n";
+        let other =
+            "\nuse regex::Regex;\npub fn f() { regex::escape(\"\"); rand::random::<u8>(); }";
+        let analysis = analyze_sources(None, &[entry, other]).unwrap();
+        assert_eq!(
+            analysis.locations["rand"].0,
+            Location { source: 0, line: 2 }
+        );
+        assert_eq!(
+            analysis.locations["regex"].0,
+            Location { source: 1, line: 2 }
+        );
+    }
+
+    #[test]
+    fn test_async_main() {
+        let source = "use std::io;\n\nasync fn main() -> io::Result<()> {\n    Ok(())\n}\n";
+        assert_eq!(
+            async_main(source).unwrap(),
+            Some(AsyncMain {
+                line: 3,
+                column: 9,
+                output: "-> io :: Result < () >".into(),
+            })
+        );
+
+        assert_eq!(async_main("fn main() {}").unwrap(), None);
+        assert_eq!(
+            async_main("#[tokio::main]\nasync fn main() {}").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_bench_harness() {
+        let libtest = "#![feature(test)]\nextern crate test;\nmod benches { #[bench] fn add(b: &mut test::Bencher) {} }";
+        assert_eq!(bench_harness(libtest).unwrap(), Some(BenchHarness::Libtest));
+
+        let criterion = "use criterion::*;\nfn add(c: &mut Criterion) {}\ncriterion_group!(benches, add);\ncriterion_main!(benches);";
+        assert_eq!(
+            bench_harness(criterion).unwrap(),
+            Some(BenchHarness::Criterion)
+        );
+
+        assert_eq!(bench_harness("#[test] fn add() {}").unwrap(), None);
+    }
+
+    #[test]
+    fn test_unstable_features() {
+        let source = "#!/usr/bin/env run-cargo-script\n//# serde = \"1\"\n#![feature(never_type, let_chains)]\n#![allow(unused)]\n\nfn main() {}\n";
+        assert_eq!(
+            unstable_features(source).unwrap(),
+            ["never_type", "let_chains"]
+        );
+
+        assert!(unstable_features("#[feature(never_type)]\nfn main() {}")
+            .unwrap()
+            .is_empty());
+    }
 }
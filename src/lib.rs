@@ -1,4 +1,9 @@
 mod cargo;
-mod errors;
+mod config;
+pub mod errors;
+mod infer;
+pub mod lint;
 pub mod options;
+mod registry;
+mod runtime;
 pub mod steps;
@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::cargo::parse_header;
+use crate::config::Config;
+use crate::errors::CargoPlayError;
+use crate::infer;
+use crate::options::{AsyncRuntime, LintOptions};
+use crate::registry::Registry;
+use crate::steps::*;
+
+/// A problem found in the `//#` headers.
+#[derive(Debug, PartialEq)]
+pub struct Warning {
+    pub message: String,
+    pub file: String,
+    pub line: usize,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "warning: {}\n  --> {}:{}",
+            self.message, self.file, self.line
+        )
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.replace('-', "_")
+}
+
+/// Checks the headers of the inputs for declarations that don't parse and crates declared several
+/// times with different requirements. With `unused`, dependencies the sources never use are
+/// reported too, which needs the analysis of the sources. `inputs` names the inputs as returned by
+/// `input_names`.
+pub fn check(
+    stdin: Option<&str>,
+    sources: &[&str],
+    inputs: &[String],
+    unused: bool,
+) -> Result<Vec<Warning>, CargoPlayError> {
+    let mut warnings = Vec::new();
+    let warn = |warnings: &mut Vec<Warning>, header: &Header, message: String| {
+        warnings.push(Warning {
+            message,
+            file: inputs[header.source].clone(),
+            line: header.line,
+        })
+    };
+
+    // first declaration of each crate, by normalized name
+    let mut declared: Vec<(String, toml::Value, Header)> = Vec::new();
+    for header in extract_headers(stdin, sources) {
+        let table = match parse_header(&header, inputs) {
+            Ok(table) => table,
+            Err(CargoPlayError::HeaderError(diagnostic)) => {
+                let hint = diagnostic
+                    .suggestion
+                    .map(|suggestion| format!(" (did you mean `{}`?)", suggestion))
+                    .unwrap_or_default();
+                warn(
+                    &mut warnings,
+                    &header,
+                    format!("header doesn't parse: {}{}", diagnostic.message, hint),
+                );
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+
+        for (name, requirement) in table {
+            let first = declared
+                .iter()
+                .find(|(known, _, _)| normalize(known) == normalize(&name));
+            match first {
+                Some((_, known, first)) if *known != requirement => warn(
+                    &mut warnings,
+                    &header,
+                    format!(
+                        "`{}` is declared again with a different requirement (first declared at \
+                         {}:{})",
+                        name, inputs[first.source], first.line
+                    ),
+                ),
+                Some(_) => {}
+                None => declared.push((name, requirement, header.clone())),
+            }
+        }
+    }
+
+    // sources that don't parse yet can't tell which crates they use
+    let analysis = if unused {
+        infer::analyze_sources(stdin, sources).ok()
+    } else {
+        None
+    };
+    if let Some(analysis) = analysis {
+        let config = Config::load()?;
+        let registry = Registry::open();
+        let mut used: HashSet<String> = analysis
+            .crates
+            .iter()
+            .flat_map(|name| {
+                vec![
+                    normalize(name),
+                    normalize(&infer::package_name(name, &config, &registry)),
+                ]
+            })
+            .collect();
+        used.extend(shim_runtime(
+            stdin.or_else(|| sources.first().copied()),
+            &declared,
+        ));
+
+        for (name, requirement, header) in &declared {
+            let package = requirement
+                .get("package")
+                .and_then(toml::Value::as_str)
+                .unwrap_or(name);
+            if !used.contains(&normalize(name)) && !used.contains(&normalize(package)) {
+                warn(
+                    &mut warnings,
+                    header,
+                    format!("`{}` is declared but never used", name),
+                );
+            }
+        }
+    }
+
+    warnings.sort_by_key(|warning| {
+        let input = inputs.iter().position(|input| *input == warning.file);
+        (input, warning.line)
+    });
+    Ok(warnings)
+}
+
+/// The runtime crate driving an unannotated `async fn main`, when the headers declare one: the
+/// generated entry point uses it even if the sources never mention it.
+fn shim_runtime(entry: Option<&str>, declared: &[(String, toml::Value, Header)]) -> Option<String> {
+    entry.and_then(|entry| infer::async_main(entry).ok().flatten())?;
+    AsyncRuntime::CRATES
+        .iter()
+        .map(|(name, _)| name.to_string())
+        .find(|name| {
+            declared
+                .iter()
+                .any(|(known, _, _)| normalize(known) == *name)
+        })
+}
+
+/// Reports the problems in the headers of the scripts. Exits with 1 when there are any.
+pub fn lint(options: LintOptions) -> Result<i32, CargoPlayError> {
+    let files = read_files(&options.src)?;
+    let sources: Vec<&str> = files.iter().map(|(source, _)| -> &str { source }).collect();
+    let warnings = check(None, &sources, &input_names(None, &files), true)?;
+    for warning in &warnings {
+        eprintln!("{}", warning);
+    }
+
+    if warnings.is_empty() {
+        Ok(0)
+    } else {
+        eprintln!("{} warning(s) emitted", warnings.len());
+        Ok(1)
+    }
+}
@@ -1,87 +1,181 @@
+mod batch;
+mod bench;
 mod cargo;
+mod config;
+mod emit;
 mod errors;
+mod events;
+mod fix;
+mod format;
+mod ide;
 mod infer;
+mod lint;
+mod matrix;
 mod options;
+mod registry;
+mod runtime;
+mod server;
 mod steps;
+mod timing;
+mod watch;
 
-use std::collections::HashSet;
 use std::iter::Iterator;
-use std::process::{Command, Stdio};
+use std::process::{Command, ExitStatus, Stdio};
 use std::vec::Vec;
 
-use crate::errors::CargoPlayError;
-use crate::options::Options;
+use crate::errors::{CargoPlayError, EXIT_BUILD, EXIT_USAGE};
+use structopt::StructOpt;
+
+use crate::options::{Emit, FmtOptions, LintOptions, Options, RunAllOptions, ServeOptions};
 use crate::steps::*;
 
-fn main() -> Result<(), CargoPlayError> {
+fn main() {
+    match run() {
+        Ok(code) => std::process::exit(code),
+        Err(err) => {
+            eprintln!("error: {}", err.chain());
+            std::process::exit(err.exit_code());
+        }
+    }
+}
+
+/// Runs cargo-play and returns the exit code: the program's own one when it ran, or one of the
+/// codes in `errors` when cargo-play or the build failed.
+fn run() -> Result<i32, CargoPlayError> {
     let args = std::env::args().collect::<Vec<_>>();
-    let opt = Options::parse(args);
-    if opt.is_err() {
-        return Ok(());
+
+    if let Some((name, args)) = options::subcommand(&args) {
+        return match name {
+            "run-all" => batch::run_all(parse_args(RunAllOptions::from_iter_safe(args))),
+            "serve" => server::serve(parse_args(ServeOptions::from_iter_safe(args))),
+            "lint" => lint::lint(parse_args(LintOptions::from_iter_safe(args))),
+            "fmt" => format::format(parse_args(FmtOptions::from_iter_safe(args))),
+            _ => unreachable!(),
+        };
+    }
+
+    let mut opt = parse_args(Options::parse(args));
+    if let Some(toolchain) = opt.toolchain.as_ref() {
+        check_toolchain(toolchain)?;
     }
-    let opt = opt.unwrap();
 
-    let src_hash = opt.src_hash();
-    let package_name = format!("p{}", src_hash);
     let temp = temp_dir(opt.temp_dirname());
 
     if opt.cached && temp.exists() {
-        let mut bin_path = temp.join("target");
-        if opt.release {
-            bin_path.push("release");
-        } else {
-            bin_path.push("debug");
-        }
-        // TODO reuse logic to formulate package name, i.e. to_lowercase
-        bin_path.push(&package_name.to_lowercase());
+        let bin_path = binary_path(&opt, &temp);
         if bin_path.exists() {
-            let mut cmd = Command::new(bin_path);
-            return cmd
+            let status = Command::new(bin_path)
                 .args(opt.args)
                 .stderr(Stdio::inherit())
                 .stdout(Stdio::inherit())
-                .status()
-                .map(|_| ())
-                .map_err(CargoPlayError::from);
+                .status()?;
+            exit_with(status);
         }
     }
 
+    if opt.ide_setup {
+        return ide::setup(&opt);
+    }
+
+    if opt.watch {
+        return watch::watch(&opt);
+    }
+
     let stdin = if opt.stdin { Some(read_stdin()?) } else { None };
     let files = read_files(&opt.src)?;
-    let sources: Vec<&str> = files.iter().map(|(source, _)| -> &str { source }).collect();
-    let dependencies = extract_headers(stdin.as_deref(), &sources);
-
-    let infers = if opt.infer {
-        infer::analyze_sources(stdin.as_deref(), &sources)?
-    } else {
-        HashSet::new()
-    };
-
-    if opt.clean {
-        rmtemp(&temp);
-    }
-    mktemp(&temp);
-    write_cargo_toml(
-        &temp,
-        package_name,
-        dependencies,
-        opt.edition.clone(),
-        infers,
-    )?;
-    copy_sources(&temp, stdin.as_deref(), &files)?;
-
-    let end = if let Some(save) = opt.save {
-        copy_project(&temp, &save)?
-    } else {
-        run_cargo_build(&opt, &temp)?
-    };
-
-    match end.code() {
-        Some(code) => std::process::exit(code),
-        None => std::process::exit(-1),
+
+    if opt.print_manifest || opt.write_headers {
+        let project = generate_project(&opt, &temp, stdin.as_deref(), &files)?;
+        if opt.print_manifest {
+            print!("{}", project.annotated_manifest()?);
+        }
+        if opt.write_headers {
+            let headers = project.inferred_headers();
+            let (source, path) = &files[0];
+            write_headers(path, source, &headers)?;
+            eprintln!("Added {} header(s) to {}", headers.len(), path.display());
+        }
+        return Ok(0);
+    }
+
+    if let Some(toolchain) = nightly_toolchain(&opt, stdin.as_deref(), &files)? {
+        opt.toolchain = Some(toolchain);
+    }
+
+    if opt.emit == Some(Emit::Hir) && opt.toolchain.is_none() {
+        opt.toolchain = nightly_for(&opt, "--emit hir")?;
+    }
+
+    if !opt.matrix.is_empty() {
+        return matrix::run_matrix(&opt, stdin.as_deref(), &files);
+    }
+
+    if let Some(kind) = opt.emit {
+        prepare_project(&opt, &temp, stdin.as_deref(), &files)?;
+        return emit::emit(&opt, &temp, kind);
+    }
+
+    if opt.clippy || opt.fix {
+        let map = prepare_project(&opt, &temp, stdin.as_deref(), &files)?;
+        return fix::fix(&opt, &temp, &map, &files);
+    }
+
+    if opt.message_format.as_deref() == Some("json") {
+        let map = prepare_project(&opt, &temp, stdin.as_deref(), &files)?;
+        return events::run(&opt, &temp, &map, &events::Stdout);
+    }
+
+    prepare_project(&opt, &temp, stdin.as_deref(), &files)?;
+
+    if let Some(save) = opt.save {
+        return copy_project(&temp, &save).map(exit_code);
+    }
+
+    if let Some(runs) = opt.time_runs {
+        return timing::time_runs(&opt, &temp, runs);
+    }
+
+    if opt.bench {
+        let entry = stdin.as_deref().unwrap_or(&files[0].0);
+        return bench::bench(&opt, &temp, entry);
+    }
+
+    match run_project(&opt, &temp)? {
+        Outcome::BuildFailed => Ok(EXIT_BUILD),
+        Outcome::Finished(status) => exit_with(status),
     }
 }
 
+/// Unwraps parsed arguments, or exits after printing the help, the version or the usage error.
+fn parse_args<T>(parsed: Result<T, structopt::clap::Error>) -> T {
+    match parsed {
+        Ok(options) => options,
+        Err(err) if err.use_stderr() => {
+            eprintln!("{}", err.message);
+            std::process::exit(EXIT_USAGE);
+        }
+        Err(err) => {
+            println!("{}", err.message);
+            std::process::exit(0);
+        }
+    }
+}
+
+/// Exits with the status of the program cargo-play ran. A program killed by a signal is killed
+/// again by the same signal, so that the shell sees what happened to it.
+fn exit_with(status: ExitStatus) -> ! {
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        // SAFETY: restoring the default disposition and raising the signal only affect this
+        // process, which has nothing left to clean up.
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+    std::process::exit(exit_code(status))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -102,14 +196,58 @@ mod tests {
 //# line 4"#,
         ]
         .into_iter()
-        .map(Into::into)
         .collect();
         let result = extract_headers(stdin.as_deref(), &inputs);
 
         assert_eq!(result.len(), 4);
-        assert_eq!(result[0], String::from("line 1"));
-        assert_eq!(result[1], String::from("line 2"));
-        assert_eq!(result[2], String::from("line 1"));
-        assert_eq!(result[3], String::from("line 2"));
+        assert_eq!(result[0].text, String::from("line 1"));
+        assert_eq!(result[1].text, String::from("line 2"));
+        assert_eq!(result[2].text, String::from("line 1"));
+        assert_eq!(result[3].text, String::from("line 2"));
+    }
+
+    #[test]
+    fn test_extract_header_locations() {
+        let inputs = [
+            "#!/usr/bin/env cargo-play\n\n//# rand = \"*\"\nfn main() {}",
+            "//# a\n//#\n//# b",
+        ];
+        let result = extract_headers(Some("//# stdin"), &inputs);
+
+        let located: Vec<(&str, usize, usize)> = result
+            .iter()
+            .map(|header| (header.text.as_str(), header.source, header.line))
+            .collect();
+        assert_eq!(
+            located,
+            [
+                ("stdin", 0, 1),
+                ("rand = \"*\"", 1, 3),
+                ("a", 2, 1),
+                ("b", 2, 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_edition_directive() {
+        let edition = |source| edition_directive(source).unwrap().map(String::from);
+
+        assert_eq!(
+            edition("//@ edition: 2024\nfn main() {}"),
+            Some("2024".into())
+        );
+        assert_eq!(
+            edition("//# rand = \"*\"\n//@ edition 2015\n"),
+            Some("2015".into())
+        );
+        assert_eq!(edition("// edition: 2018\nfn main() {}"), None);
+        assert_eq!(
+            edition("#!/usr/bin/env cargo-play\n\n// Notes\n//@ edition: 2018\n"),
+            Some("2018".into())
+        );
+        assert_eq!(edition("fn main() {}\n//@ edition: 2018\n"), None);
+        assert_eq!(edition("#![allow(unused)]\n//@ edition: 2018\n"), None);
+        assert!(edition_directive("//@ edition: next").is_err());
     }
 }
@@ -0,0 +1,206 @@
+use std::ffi::OsString;
+use std::path::Path;
+use std::process::{Output, Stdio};
+
+use similar::TextDiff;
+
+use crate::errors::CargoPlayError;
+use crate::options::{MatrixAxis, Options, RustEdition};
+use crate::steps::*;
+
+/// What one combination of the matrix did.
+struct Run {
+    /// Values of the axes, in the order they were given
+    values: Vec<String>,
+    /// Exit code of the program, `None` when it failed to build
+    exit_code: Option<i32>,
+    /// Output of the program, or of the build when it failed
+    stdout: String,
+    stderr: String,
+}
+
+impl Run {
+    fn label(&self) -> String {
+        self.values.join(" ")
+    }
+}
+
+/// Builds and runs every combination of the `--matrix` axes, each in its own cache directory,
+/// then prints their exit codes side by side and how their outputs differ. Returns 0 when every
+/// combination succeeded with the same output.
+pub fn run_matrix(
+    options: &Options,
+    stdin: Option<&str>,
+    files: &[(String, &Path)],
+) -> Result<i32, CargoPlayError> {
+    let mut toolchains = vec![options.toolchain.clone()];
+    let mut editions = vec![options.edition.clone()];
+    let mut axes = Vec::new();
+
+    for axis in &options.matrix {
+        let (name, duplicate) = match axis {
+            MatrixAxis::Toolchain(values) => {
+                let duplicate = axes.contains(&"toolchain");
+                toolchains = values.iter().cloned().map(Some).collect();
+                ("toolchain", duplicate)
+            }
+            MatrixAxis::Edition(values) => {
+                let duplicate = axes.contains(&"edition");
+                editions = values.iter().cloned().map(Some).collect();
+                ("edition", duplicate)
+            }
+        };
+        if duplicate {
+            return Err(CargoPlayError::InvalidMatrix(name.to_string()));
+        }
+        axes.push(name);
+    }
+
+    for toolchain in toolchains.iter().flatten() {
+        check_toolchain(toolchain)?;
+    }
+
+    let mut runs = Vec::new();
+    for toolchain in &toolchains {
+        for edition in &editions {
+            let values = axes
+                .iter()
+                .map(|axis| match *axis {
+                    "toolchain" => toolchain.clone().unwrap_or_default(),
+                    _ => edition.clone().map(String::from).unwrap_or_default(),
+                })
+                .collect();
+            runs.push(run_combination(
+                options, toolchain, edition, values, stdin, files,
+            )?);
+        }
+    }
+
+    print_summary(&axes, &runs);
+    let same = print_diffs(&runs);
+
+    Ok(if same && runs.iter().all(|run| run.exit_code == Some(0)) {
+        0
+    } else {
+        1
+    })
+}
+
+fn run_combination(
+    options: &Options,
+    toolchain: &Option<String>,
+    edition: &Option<RustEdition>,
+    values: Vec<String>,
+    stdin: Option<&str>,
+    files: &[(String, &Path)],
+) -> Result<Run, CargoPlayError> {
+    let opt = Options {
+        toolchain: toolchain.clone(),
+        edition: edition.clone(),
+        matrix: Vec::new(),
+        quiet: true,
+        verbose: 0,
+        ..options.clone()
+    };
+
+    let mut dirname = OsString::from(opt.temp_dirname());
+    for value in &values {
+        dirname.push(".");
+        dirname.push(value);
+    }
+    let temp = temp_dir(dirname.into());
+
+    eprintln!("[cargo-play] running {}", values.join(" "));
+    prepare_project(&opt, &temp, stdin, files)?;
+
+    let captured = |output: Output, exit_code| Run {
+        values: values.clone(),
+        exit_code,
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    };
+
+    // build first, so that a broken build isn't mistaken for a failing program
+    if opt.subcommand() == "run" {
+        let build = cargo_subcommand(&opt, &temp, "build")
+            .stdin(Stdio::null())
+            .output()?;
+        if !build.status.success() {
+            return Ok(captured(build, None));
+        }
+    }
+
+    let output = cargo_command(&opt, &temp).stdin(Stdio::null()).output()?;
+    let exit_code = exit_code(output.status);
+    Ok(captured(output, Some(exit_code)))
+}
+
+fn print_summary(axes: &[&str], runs: &[Run]) {
+    let widths: Vec<usize> = axes
+        .iter()
+        .enumerate()
+        .map(|(index, axis)| {
+            runs.iter()
+                .map(|run| run.values[index].len())
+                .chain(Some(axis.len()))
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    println!();
+    for (axis, width) in axes.iter().zip(&widths) {
+        print!("{:<width$}  ", axis, width = width);
+    }
+    println!("exit");
+
+    for run in runs {
+        for (value, width) in run.values.iter().zip(&widths) {
+            print!("{:<width$}  ", value, width = width);
+        }
+        match run.exit_code {
+            Some(code) => println!("{}", code),
+            None => println!("build failed"),
+        }
+    }
+}
+
+/// Groups the combinations printing the same output and prints how the output of each group
+/// differs from the first one, returning whether there is a single group.
+fn print_diffs(runs: &[Run]) -> bool {
+    let mut groups: Vec<(&Run, Vec<String>)> = Vec::new();
+    for run in runs {
+        match groups
+            .iter_mut()
+            .find(|(first, _)| first.stdout == run.stdout && first.stderr == run.stderr)
+        {
+            Some((_, labels)) => labels.push(run.label()),
+            None => groups.push((run, vec![run.label()])),
+        }
+    }
+
+    if groups.len() == 1 {
+        println!("\nevery combination printed the same output");
+        return true;
+    }
+
+    let (first, first_labels) = &groups[0];
+    for (run, labels) in &groups[1..] {
+        for (stream, old, new) in [
+            ("stdout", &first.stdout, &run.stdout),
+            ("stderr", &first.stderr, &run.stderr),
+        ] {
+            if old != new {
+                println!();
+                print!(
+                    "{}",
+                    TextDiff::from_lines(old, new).unified_diff().header(
+                        &format!("{} ({})", first_labels.join(", "), stream),
+                        &format!("{} ({})", labels.join(", "), stream)
+                    )
+                );
+            }
+        }
+    }
+    false
+}
@@ -1,5 +1,4 @@
 use std::ffi::{OsStr, OsString};
-use std::iter::FromIterator;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::vec::Vec;
@@ -9,51 +8,147 @@ use structopt::StructOpt;
 
 use crate::errors::CargoPlayError;
 
-#[derive(Debug, Clone)]
-pub enum RustEdition {
-    E2015,
-    E2018,
-    E2021,
+/// A Rust edition, such as `2021`. Which ones are available is up to the toolchain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RustEdition(String);
+
+impl RustEdition {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for RustEdition {
+    fn default() -> Self {
+        RustEdition("2021".into())
+    }
 }
 
 impl FromStr for RustEdition {
     type Err = CargoPlayError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s == "2021" {
-            Ok(RustEdition::E2021)
-        } else if s == "2018" {
-            Ok(RustEdition::E2018)
-        } else if s == "2015" {
-            Ok(RustEdition::E2015)
+        if s.len() == 4 && s.chars().all(|c| c.is_ascii_digit()) {
+            Ok(RustEdition(s.into()))
         } else {
             Err(CargoPlayError::InvalidEdition(s.into()))
         }
     }
 }
 
-impl Into<String> for RustEdition {
-    fn into(self) -> String {
-        match self {
-            RustEdition::E2015 => "2015".into(),
-            RustEdition::E2018 => "2018".into(),
-            RustEdition::E2021 => "2021".into(),
+impl From<RustEdition> for String {
+    fn from(edition: RustEdition) -> String {
+        edition.0
+    }
+}
+
+/// Executor driving an unannotated `async fn main`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AsyncRuntime {
+    Tokio,
+    AsyncStd,
+    Smol,
+    /// A minimal `block_on` that needs no dependency
+    Builtin,
+}
+
+impl FromStr for AsyncRuntime {
+    type Err = CargoPlayError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tokio" => Ok(AsyncRuntime::Tokio),
+            "async-std" => Ok(AsyncRuntime::AsyncStd),
+            "smol" => Ok(AsyncRuntime::Smol),
+            "builtin" => Ok(AsyncRuntime::Builtin),
+            _ => Err(CargoPlayError::InvalidAsyncRuntime(s.into())),
         }
     }
 }
 
-impl Default for RustEdition {
-    fn default() -> Self {
-        RustEdition::E2021
+/// Compiler output printed by `--emit`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+    Asm,
+    LlvmIr,
+    Mir,
+    Hir,
+    /// WebAssembly in its text format
+    Wasm,
+}
+
+impl FromStr for Emit {
+    type Err = CargoPlayError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "asm" => Ok(Emit::Asm),
+            "llvm-ir" => Ok(Emit::LlvmIr),
+            "mir" => Ok(Emit::Mir),
+            "hir" => Ok(Emit::Hir),
+            "wasm" => Ok(Emit::Wasm),
+            _ => Err(CargoPlayError::InvalidEmit(s.into())),
+        }
+    }
+}
+
+/// One dimension of `--matrix`, e.g. `toolchain=stable,nightly` or `edition=2018,2021`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatrixAxis {
+    Toolchain(Vec<String>),
+    Edition(Vec<RustEdition>),
+}
+
+impl FromStr for MatrixAxis {
+    type Err = CargoPlayError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || CargoPlayError::InvalidMatrix(s.into());
+        let (axis, values) = s.split_once('=').ok_or_else(invalid)?;
+        let values: Vec<&str> = values.split(',').map(str::trim).collect();
+        if values.iter().any(|value| value.is_empty()) {
+            return Err(invalid());
+        }
+
+        match axis {
+            "toolchain" => Ok(MatrixAxis::Toolchain(
+                values.into_iter().map(String::from).collect(),
+            )),
+            "edition" => Ok(MatrixAxis::Edition(
+                values
+                    .into_iter()
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()?,
+            )),
+            _ => Err(invalid()),
+        }
     }
 }
 
-#[derive(Debug, StructOpt, Default)]
+/// Other ways of building the script, which `--clippy`, `--fix`, `--bench`, `--time-runs` and
+/// `--emit` can't be combined with.
+const MODE_CONFLICTS: &[&str] = &[
+    "test",
+    "check",
+    "expand",
+    "mode",
+    "message-format",
+    "matrix",
+    "cached",
+    "ide-setup",
+];
+
+/// Flags saving, watching or printing the generated project, which `--clippy`, `--fix`,
+/// `--time-runs` and `--emit` can't be combined with either.
+const PROJECT_CONFLICTS: &[&str] = &["save", "watch", "print-manifest", "write-headers"];
+
+#[derive(Debug, Clone, StructOpt, Default)]
 #[structopt(
     name = "cargo-play",
     about = "Run your Rust program without Cargo.toml"
 )]
 pub struct Options {
+    #[allow(dead_code)]
     #[structopt(short = "d", long = "debug", hidden = true)]
     pub debug: bool,
 
@@ -78,6 +173,64 @@ pub struct Options {
     #[structopt(long = "expand", group = "modegroup")]
     pub expand: bool,
 
+    /// Apply the suggestions of Clippy to your files (runs `cargo clippy --fix`). Arguments after
+    /// `--` are passed to Clippy
+    #[structopt(
+        long = "clippy",
+        conflicts_with_all = MODE_CONFLICTS,
+        conflicts_with_all = PROJECT_CONFLICTS,
+        conflicts_with_all = &["fix", "stdin", "emit"]
+    )]
+    pub clippy: bool,
+
+    /// Apply the suggestions of the compiler to your files (runs `cargo fix`)
+    #[structopt(
+        long = "fix",
+        conflicts_with_all = MODE_CONFLICTS,
+        conflicts_with_all = PROJECT_CONFLICTS,
+        conflicts_with_all = &["stdin", "emit"]
+    )]
+    pub fix: bool,
+
+    /// Run the `#[bench]` functions or the criterion benchmarks of your code in release mode
+    #[structopt(
+        long = "bench",
+        conflicts_with_all = MODE_CONFLICTS,
+        conflicts_with_all = &["clippy", "fix", "release", "emit"]
+    )]
+    pub bench: bool,
+
+    #[structopt(
+        long = "baseline",
+        requires = "bench",
+        conflicts_with = "save-baseline"
+    )]
+    /// Compare the benchmarks with the results saved under this name
+    pub baseline: Option<String>,
+
+    #[structopt(long = "save-baseline", requires = "bench")]
+    /// Save the results of the benchmarks under this name, to compare later runs with them
+    pub save_baseline: Option<String>,
+
+    #[structopt(
+        long = "time-runs",
+        validator = at_least_one,
+        conflicts_with_all = MODE_CONFLICTS,
+        conflicts_with_all = PROJECT_CONFLICTS,
+        conflicts_with_all = &["clippy", "fix", "bench", "emit"]
+    )]
+    /// Build the program in release mode and time this many runs of it, reporting the wall time
+    /// and peak memory usage
+    pub time_runs: Option<usize>,
+
+    #[structopt(long = "warmup", requires = "time-runs")]
+    /// Number of runs before the timed ones, to warm up caches [default: 1]
+    pub warmup: Option<usize>,
+
+    #[structopt(long = "export-json", requires = "time-runs")]
+    /// Write the timings of `--time-runs` as JSON to the given path
+    pub export_json: Option<PathBuf>,
+
     #[structopt(short = "t", long = "toolchain", hidden = true)]
     pub toolchain: Option<String>,
 
@@ -89,14 +242,9 @@ pub struct Options {
     /// Paths to your source code files
     pub src: Vec<PathBuf>,
 
-    #[structopt(
-        short = "e",
-        long = "edition",
-        default_value = "2021",
-        possible_values = &["2015", "2018", "2021"]
-    )]
-    /// Specify Rust edition
-    pub edition: RustEdition,
+    #[structopt(short = "e", long = "edition")]
+    /// Specify Rust edition, e.g. 2024 [default: the script's `//@ edition` directive, or 2021]
+    pub edition: Option<RustEdition>,
 
     #[structopt(long = "release")]
     /// Build program in release mode
@@ -109,6 +257,10 @@ pub struct Options {
     /// Disable output from Cargo (equivlant to `cargo run --quiet`)
     pub quiet: bool,
 
+    #[structopt(long = "lint")]
+    /// Also warn about dependencies declared in the headers that the sources never use
+    pub lint: bool,
+
     #[structopt(long = "verbose", short = "v", parse(from_occurrences))]
     /// Set Cargo verbose level
     pub verbose: u16,
@@ -117,9 +269,18 @@ pub struct Options {
     /// Use stdin as main.rs
     pub stdin: bool,
 
-    #[structopt(long = "cargo-option")]
-    /// Customize flags passing to Cargo
-    pub cargo_option: Option<String>,
+    #[structopt(
+        long = "cargo-option",
+        number_of_values = 1,
+        allow_hyphen_values = true,
+        parse(try_from_str = shell_words::split)
+    )]
+    /// Customize flags passing to Cargo, split like a shell would (repeatable)
+    pub cargo_option: Vec<Vec<String>>,
+
+    #[structopt(long = "cargo-arg", number_of_values = 1, allow_hyphen_values = true)]
+    /// Pass one argument to Cargo as is (repeatable)
+    pub cargo_arg: Vec<String>,
 
     #[structopt(long = "save")]
     /// Generate a Cargo project based on inputs
@@ -129,11 +290,167 @@ pub struct Options {
     #[structopt(long = "infer", short = "i")]
     pub infer: bool,
 
+    #[structopt(long = "watch", short = "w", conflicts_with_all = &["stdin", "save", "cached"])]
+    /// Rebuild and rerun whenever a source file, module or included asset changes
+    pub watch: bool,
+
+    #[structopt(long = "clear", requires = "watch")]
+    /// Clear the screen before each run in watch mode
+    pub clear: bool,
+
+    #[structopt(long = "ide-setup", conflicts_with_all = &["stdin", "save", "message-format"])]
+    /// Generate a project rust-analyzer can load for the script instead of running it. Combine
+    /// with `--watch` to keep it updated when the headers change
+    pub ide_setup: bool,
+
+    #[structopt(
+        long = "message-format",
+        possible_values = &["human", "json"],
+        conflicts_with_all = &["save", "watch"]
+    )]
+    /// Output format, `json` prints line-delimited JSON events for editor integrations [default:
+    /// human]
+    pub message_format: Option<String>,
+
+    #[structopt(
+        long = "print-manifest",
+        conflicts_with_all = &["save", "watch", "ide-setup", "message-format"]
+    )]
+    /// Print the generated Cargo.toml, noting where each dependency comes from, instead of running
+    pub print_manifest: bool,
+
+    #[structopt(
+        long = "write-headers",
+        requires = "infer",
+        conflicts_with_all = &["stdin", "save", "watch", "ide-setup", "message-format"]
+    )]
+    /// Add the inferred dependencies to the `//#` headers of the first file instead of running
+    pub write_headers: bool,
+
+    #[structopt(
+        long = "async-runtime",
+        possible_values = &["tokio", "async-std", "smol", "builtin"]
+    )]
+    /// Runtime driving an `async fn main` [default: the runtime the script depends on, or tokio]
+    pub async_runtime: Option<AsyncRuntime>,
+
+    #[structopt(
+        long = "matrix",
+        number_of_values = 1,
+        conflicts_with_all = &["save", "watch", "ide-setup", "message-format", "print-manifest", "write-headers", "cached"]
+    )]
+    /// Build and run every combination of toolchains and editions, e.g. `--matrix
+    /// toolchain=stable,nightly --matrix edition=2018,2021`, then compare their outputs (repeatable)
+    pub matrix: Vec<MatrixAxis>,
+
+    #[structopt(
+        long = "emit",
+        possible_values = &["asm", "llvm-ir", "mir", "hir", "wasm"],
+        conflicts_with_all = MODE_CONFLICTS,
+        conflicts_with_all = PROJECT_CONFLICTS
+    )]
+    /// Print the assembly, LLVM IR, MIR, HIR or WebAssembly of the program instead of running it
+    pub emit: Option<Emit>,
+
+    #[structopt(long = "filter", requires = "emit")]
+    /// Only print the given function of `--emit`, e.g. `main` or `Point::new`
+    pub filter: Option<String>,
+
     #[structopt(multiple = true, last = true)]
     /// Arguments passed to the underlying program
     pub args: Vec<String>,
 }
 
+/// Options of `cargo play run-all`
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "cargo-play-run-all",
+    about = "Build and run a directory of scripts as a test suite"
+)]
+pub struct RunAllOptions {
+    #[structopt(required = true)]
+    /// Directories or glob patterns (e.g. `repro/**/*.rs`) of the scripts to run
+    pub targets: Vec<String>,
+
+    #[structopt(short = "j", long = "jobs")]
+    /// Number of scripts to build and run in parallel [default: number of CPUs]
+    pub jobs: Option<usize>,
+
+    #[structopt(short = "e", long = "edition")]
+    /// Specify Rust edition, e.g. 2024 [default: each script's `//@ edition` directive, or 2021]
+    pub edition: Option<RustEdition>,
+
+    #[structopt(long = "release")]
+    /// Build programs in release mode
+    pub release: bool,
+
+    /// [experimental] Automatically infers crate dependency
+    #[structopt(long = "infer", short = "i")]
+    pub infer: bool,
+
+    #[structopt(long = "junit")]
+    /// Write a JUnit XML report to the given path
+    pub junit: Option<PathBuf>,
+
+    #[structopt(long = "json")]
+    /// Write a JSON report to the given path
+    pub json: Option<PathBuf>,
+}
+
+/// Options of `cargo play serve`
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "cargo-play-serve",
+    about = "Serve JSON-RPC requests over stdio for editor integrations"
+)]
+pub struct ServeOptions {}
+
+/// Options of `cargo play lint`
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "cargo-play-lint",
+    about = "Check the `//#` headers of scripts for problems"
+)]
+pub struct LintOptions {
+    #[structopt(required = true, validator = file_exist)]
+    /// Paths to your source code files
+    pub src: Vec<PathBuf>,
+}
+
+/// Options of `cargo play fmt`
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "cargo-play-fmt",
+    about = "Format scripts and their `//#` headers in place"
+)]
+pub struct FmtOptions {
+    #[structopt(required = true, validator = file_exist)]
+    /// Paths to your source code files
+    pub src: Vec<PathBuf>,
+
+    #[structopt(long = "check")]
+    /// Print the differences instead of writing the files, and exit with 1 when there are some
+    pub check: bool,
+}
+
+/// Names of the subcommands accepted in place of source files.
+const SUBCOMMANDS: &[&str] = &["run-all", "serve", "lint", "fmt"];
+
+/// Returns the subcommand named by the first argument (after the optional `play` inserted by
+/// Cargo) together with the arguments to parse it with.
+pub fn subcommand(args: &[String]) -> Option<(&str, Vec<String>)> {
+    let skip = if args.get(1).map(String::as_str) == Some("play") {
+        2
+    } else {
+        1
+    };
+    let name = SUBCOMMANDS
+        .iter()
+        .find(|name| args.get(skip).map(String::as_str) == Some(**name))?;
+
+    Some((name, args[skip..].to_vec()))
+}
+
 impl Options {
     #[allow(unused)]
     /// Convenient constructor for testing
@@ -161,34 +478,63 @@ impl Options {
         bs58::encode(hash.finalize()).into_string()
     }
 
+    /// Name of the generated package. Cargo lowercases it, so we do too.
+    pub fn package_name(&self) -> String {
+        format!("p{}", self.src_hash()).to_lowercase()
+    }
+
+    /// Cargo subcommand selected by the mode flags
+    pub fn subcommand(&self) -> &str {
+        if self.test {
+            "test"
+        } else if self.check {
+            "check"
+        } else if self.expand {
+            "expand"
+        } else if self.bench {
+            "bench"
+        } else if self.clippy {
+            "clippy"
+        } else if self.fix {
+            "fix"
+        } else if let Some(mode) = self.mode.as_ref() {
+            mode.as_str()
+        } else {
+            "run"
+        }
+    }
+
     pub fn temp_dirname(&self) -> PathBuf {
         format!("cargo-play.{}", self.src_hash()).into()
     }
 
-    fn with_toolchain(mut self, toolchain: Option<String>) -> Self {
-        self.toolchain = toolchain;
-        self
+    /// Directory of the `--ide-setup` project. It lives next to, not inside, the cached project so
+    /// that `--clean` does not delete the path the editor was pointed at.
+    pub fn ide_dirname(&self) -> PathBuf {
+        format!("cargo-play-ide.{}", self.src_hash()).into()
     }
 
-    pub fn parse(args: Vec<String>) -> Result<Self, ()> {
-        if args.len() < 2 {
-            Self::clap().print_help().unwrap_or(());
-            return Err(());
+    fn with_toolchain(mut self, toolchain: Option<String>) -> Self {
+        if toolchain.is_some() {
+            self.toolchain = toolchain;
         }
+        self
+    }
 
-        let with_cargo = args[1] == "play";
-        let mut args = args.into_iter();
-
-        if with_cargo {
-            args.next();
+    pub fn parse(mut args: Vec<String>) -> Result<Self, structopt::clap::Error> {
+        // `cargo play` runs us as `cargo-play play ...`
+        if args.get(1).map(String::as_str) == Some("play") {
+            args.remove(0);
         }
 
-        let toolchain = args
-            .clone()
-            .find(|x| x.starts_with('+'))
-            .map(|s| String::from_iter(s.chars().skip(1)));
+        // like cargo, only take `+toolchain` right after the command so that arguments starting
+        // with `+` still reach the program
+        let toolchain = match args.get(1) {
+            Some(arg) if arg.starts_with('+') => Some(args.remove(1)[1..].to_string()),
+            _ => None,
+        };
 
-        Ok(Self::from_iter(args.filter(|x| !x.starts_with('+'))).with_toolchain(toolchain))
+        Ok(Self::from_iter_safe(args)?.with_toolchain(toolchain))
     }
 }
 
@@ -201,6 +547,14 @@ fn osstr_to_abspath(v: &OsStr) -> Result<PathBuf, OsString> {
     }
 }
 
+/// structopt compatible function to check that a count is a positive number
+fn at_least_one(v: String) -> Result<(), String> {
+    match v.parse::<usize>() {
+        Ok(count) if count > 0 => Ok(()),
+        _ => Err(format!("expected a positive number, got {:?}", v)),
+    }
+}
+
 /// structopt compataible function to check whether a file exists
 fn file_exist(v: String) -> Result<(), String> {
     let p = PathBuf::from(v);
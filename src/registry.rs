@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use semver::Version;
+use serde::Deserialize;
+
+/// Version of the cache format Cargo writes under `.cache`.
+const CACHE_VERSION: u8 = 3;
+
+/// A published version of a crate, as recorded in the index.
+#[derive(Debug, Deserialize)]
+pub struct IndexEntry {
+    pub vers: String,
+    #[serde(default)]
+    pub yanked: bool,
+}
+
+/// Read-only view of the crates.io index Cargo caches under `$CARGO_HOME/registry/index`. It
+/// never touches the network, and an empty view is used when there is no cache.
+#[derive(Debug, Default)]
+pub struct Registry {
+    roots: Vec<PathBuf>,
+}
+
+impl Registry {
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        Self { roots }
+    }
+
+    /// Opens the index caches of every registry in Cargo's home directory.
+    pub fn open() -> Self {
+        let home = env::var_os("CARGO_HOME").map(PathBuf::from).or_else(|| {
+            env::var_os("HOME")
+                .or_else(|| env::var_os("USERPROFILE"))
+                .map(|home| PathBuf::from(home).join(".cargo"))
+        });
+
+        let roots = home
+            .and_then(|home| std::fs::read_dir(home.join("registry").join("index")).ok())
+            .map(|entries| {
+                let mut roots: Vec<PathBuf> = entries
+                    .filter_map(Result::ok)
+                    .map(|entry| entry.path())
+                    .filter(|path| path.is_dir())
+                    .collect();
+                roots.sort();
+                roots
+            })
+            .unwrap_or_default();
+
+        Self::new(roots)
+    }
+
+    /// Relative path of a crate's entry in the index. Package names are ASCII, so there is none for
+    /// other names.
+    fn entry_path(name: &str) -> Option<PathBuf> {
+        if name.is_empty() || !name.is_ascii() {
+            return None;
+        }
+        let name = name.to_ascii_lowercase();
+        Some(match name.len() {
+            1 => PathBuf::from("1").join(&name),
+            2 => PathBuf::from("2").join(&name),
+            3 => PathBuf::from("3").join(&name[..1]).join(&name),
+            _ => PathBuf::from(&name[..2]).join(&name[2..4]).join(&name),
+        })
+    }
+
+    /// Files that may hold the entry of `name`: the cache written for sparse and git registries,
+    /// and the checkout of git registries.
+    fn candidates(&self, name: &str) -> impl Iterator<Item = PathBuf> + '_ {
+        let entry = Self::entry_path(name);
+        self.roots.iter().flat_map(move |root| match &entry {
+            Some(entry) => vec![root.join(".cache").join(entry), root.join(entry)],
+            None => Vec::new(),
+        })
+    }
+
+    /// Whether a package called exactly `name` is known to the cached index.
+    pub fn contains(&self, name: &str) -> bool {
+        self.candidates(name).any(|path| path.is_file())
+    }
+
+    /// Reads every version of `name` recorded in the first index that knows about it.
+    pub fn entries(&self, name: &str) -> Vec<IndexEntry> {
+        self.candidates(name)
+            .find_map(|path| {
+                let content = std::fs::read(&path).ok()?;
+                if path.components().any(|c| c.as_os_str() == ".cache") {
+                    parse_cache(&content)
+                } else {
+                    Some(parse_lines(&content))
+                }
+            })
+            .unwrap_or_default()
+    }
+
+    /// The newest version of `name` that is neither yanked nor a pre-release.
+    pub fn newest(&self, name: &str) -> Option<Version> {
+        self.entries(name)
+            .into_iter()
+            .filter(|entry| !entry.yanked)
+            .filter_map(|entry| Version::parse(&entry.vers).ok())
+            .filter(|version| version.pre.is_empty())
+            .max()
+    }
+}
+
+/// Parses an index file of a git registry checkout: one JSON document per line.
+fn parse_lines(content: &[u8]) -> Vec<IndexEntry> {
+    content
+        .split(|&byte| byte == b'\n')
+        .filter_map(|line| serde_json::from_slice(line).ok())
+        .collect()
+}
+
+/// Parses a file of the index cache: the cache version, the index format version and the index
+/// revision, followed by NUL separated pairs of version and JSON document.
+fn parse_cache(content: &[u8]) -> Option<Vec<IndexEntry>> {
+    let (&cache_version, rest) = content.split_first()?;
+    if cache_version != CACHE_VERSION || rest.len() < 4 {
+        return None;
+    }
+
+    let mut fields = rest[4..].split(|&byte| byte == 0);
+    // index revision
+    fields.next()?;
+
+    let mut entries = Vec::new();
+    while let (Some(_version), Some(json)) = (fields.next(), fields.next()) {
+        if let Ok(entry) = serde_json::from_slice(json) {
+            entries.push(entry);
+        }
+    }
+    Some(entries)
+}
+
+/// Reads the versions a lockfile resolved the direct dependencies of the `root` package to.
+pub fn locked_versions(lockfile: &Path, root: &str) -> HashMap<String, Version> {
+    #[derive(Deserialize)]
+    struct Lockfile {
+        #[serde(default)]
+        package: Vec<LockedPackage>,
+    }
+
+    #[derive(Deserialize)]
+    struct LockedPackage {
+        name: String,
+        version: String,
+        #[serde(default)]
+        dependencies: Vec<String>,
+    }
+
+    let lockfile: Lockfile = match std::fs::read_to_string(lockfile)
+        .ok()
+        .and_then(|content| toml::from_str(&content).ok())
+    {
+        Some(lockfile) => lockfile,
+        None => return HashMap::new(),
+    };
+
+    let root = match lockfile.package.iter().find(|package| package.name == root) {
+        Some(root) => root,
+        None => return HashMap::new(),
+    };
+
+    // dependencies are written as `name`, or `name version` when several versions are locked
+    root.dependencies
+        .iter()
+        .filter_map(|dependency| {
+            let mut parts = dependency.split_whitespace();
+            let name = parts.next()?;
+            let version = match parts.next() {
+                Some(version) => version,
+                None => {
+                    &lockfile
+                        .package
+                        .iter()
+                        .find(|package| package.name == name)?
+                        .version
+                }
+            };
+            Some((name.to_string(), Version::parse(version).ok()?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_path() {
+        assert_eq!(Registry::entry_path("a"), Some(PathBuf::from("1/a")));
+        assert_eq!(
+            Registry::entry_path("Serde"),
+            Some(PathBuf::from("se/rd/serde"))
+        );
+        assert_eq!(Registry::entry_path("ñu"), None);
+        assert_eq!(Registry::entry_path("日本語"), None);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::infer::AsyncMain;
+use crate::options::AsyncRuntime;
+
+/// Precedes the entry point appended to a script with an `async fn main`.
+pub const SHIM_MARKER: &str = "// @generated by cargo-play: entry point driving `async fn main`";
+
+/// The name `async fn main` is renamed to.
+pub const ASYNC_MAIN: &str = "__cargo_play_main";
+
+const BUILTIN_BLOCK_ON: &str = r#"    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        use std::task::{Context, Poll, Wake};
+
+        struct Unpark(std::thread::Thread);
+
+        impl Wake for Unpark {
+            fn wake(self: std::sync::Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = std::sync::Arc::new(Unpark(std::thread::current())).into();
+        let mut context = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            match future.as_mut().poll(&mut context) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+"#;
+
+impl AsyncRuntime {
+    /// Every runtime, with the name the sources use for its crate.
+    pub const CRATES: &'static [(&'static str, AsyncRuntime)] = &[
+        ("tokio", AsyncRuntime::Tokio),
+        ("async_std", AsyncRuntime::AsyncStd),
+        ("smol", AsyncRuntime::Smol),
+    ];
+
+    /// The crate the shim needs, as the name used in the sources, with its required features.
+    pub fn dependency(self) -> Option<(&'static str, &'static [&'static str])> {
+        match self {
+            AsyncRuntime::Tokio => Some(("tokio", &["rt-multi-thread"])),
+            AsyncRuntime::AsyncStd => Some(("async_std", &[])),
+            AsyncRuntime::Smol => Some(("smol", &[])),
+            AsyncRuntime::Builtin => None,
+        }
+    }
+
+    fn block_on(self) -> String {
+        let call = match self {
+            AsyncRuntime::Tokio => {
+                "tokio::runtime::Builder::new_multi_thread()\n        \
+                                    .enable_all()\n        \
+                                    .build()\n        \
+                                    .expect(\"failed to start the tokio runtime\")\n        \
+                                    .block_on"
+            }
+            AsyncRuntime::AsyncStd => "async_std::task::block_on",
+            AsyncRuntime::Smol => "smol::block_on",
+            AsyncRuntime::Builtin => "block_on",
+        };
+        format!("{}({}())", call, ASYNC_MAIN)
+    }
+}
+
+/// Renames the `async fn main` of `source` and appends a synchronous `main` driving it with
+/// `runtime`. Lines are left where they were so that diagnostics still point at the right place.
+pub fn add_shim(source: &str, main: &AsyncMain, runtime: AsyncRuntime) -> String {
+    let offset = source
+        .split_inclusive('\n')
+        .take(main.line - 1)
+        .map(str::len)
+        .sum::<usize>();
+    let line = &source[offset..];
+    let column = line
+        .char_indices()
+        .nth(main.column)
+        .map_or(line.len(), |(index, _)| index);
+    let start = offset + column;
+
+    let mut output = String::with_capacity(source.len() + BUILTIN_BLOCK_ON.len());
+    output.push_str(&source[..start]);
+    output.push_str(ASYNC_MAIN);
+    output.push_str(&source[start + "main".len()..]);
+    if !output.ends_with('\n') {
+        output.push('\n');
+    }
+
+    output.push('\n');
+    output.push_str(SHIM_MARKER);
+    output.push('\n');
+    if main.output.is_empty() {
+        output.push_str("fn main() {\n");
+    } else {
+        output.push_str(&format!("fn main() {} {{\n", main.output));
+    }
+    if runtime == AsyncRuntime::Builtin {
+        output.push_str(BUILTIN_BLOCK_ON);
+        output.push('\n');
+    }
+    output.push_str(&format!("    {}\n}}\n", runtime.block_on()));
+    output
+}
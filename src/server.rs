@@ -0,0 +1,364 @@
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::{Arc, Mutex};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha1::Digest;
+
+use crate::errors::CargoPlayError;
+use crate::events::{self, Event, EventSink};
+use crate::options::{Options, ServeOptions};
+use crate::steps::*;
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// Script a request operates on: a file on disk, optionally with unsaved `source` from the
+/// editor, or a standalone in-memory buffer identified by `name`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Target {
+    file: Option<PathBuf>,
+    source: Option<String>,
+    name: Option<String>,
+    args: Vec<String>,
+    edition: Option<String>,
+    release: bool,
+    infer: bool,
+}
+
+#[derive(Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new<T: ToString>(code: i64, message: T) -> Self {
+        RpcError {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<CargoPlayError> for RpcError {
+    fn from(err: CargoPlayError) -> Self {
+        RpcError::new(SERVER_ERROR, err.chain())
+    }
+}
+
+#[derive(Default)]
+struct Server {
+    /// Processes started by the requests still running, by request id. Every running request has
+    /// an entry, even before it has started a process.
+    running: Mutex<HashMap<String, Vec<Arc<Mutex<Child>>>>>,
+    /// Running requests that were cancelled, so that processes they start later are killed too.
+    cancelled: Mutex<HashSet<String>>,
+    /// Fingerprint of the inputs last written to each cached project, with its source map.
+    projects: Mutex<HashMap<PathBuf, (String, SourceMap)>>,
+    /// Serializes the requests working on the same project.
+    locks: Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>,
+}
+
+/// Forwards the events of a run as `event` notifications tagged with the request id.
+struct Notifier<'a> {
+    server: &'a Server,
+    id: &'a Value,
+}
+
+impl EventSink for Notifier<'_> {
+    fn emit(&self, event: &Event) -> Result<(), CargoPlayError> {
+        let mut params = serde_json::to_value(event).map_err(CargoPlayError::from_serde)?;
+        params["id"] = self.id.clone();
+        events::write_line(&json!({ "jsonrpc": "2.0", "method": "event", "params": params }))
+    }
+
+    fn started(&self, child: &Arc<Mutex<Child>>) {
+        let key = self.id.to_string();
+        if self.server.cancelled.lock().unwrap().contains(&key) {
+            let _ = child.lock().unwrap().kill();
+        }
+        self.server
+            .running
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_default()
+            .push(child.clone());
+    }
+}
+
+/// Serves line-delimited JSON-RPC 2.0 requests from stdin until it is closed. `run`, `check` and
+/// `test` are processed concurrently and stream their progress as `event` notifications before
+/// responding with the exit code.
+pub fn serve(_options: ServeOptions) -> Result<i32, CargoPlayError> {
+    let server = Server::default();
+    let stdin = std::io::stdin();
+
+    std::thread::scope(|scope| {
+        for line in stdin.lock().lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let request: Request = match serde_json::from_str(&line) {
+                Ok(request) => request,
+                Err(err) => {
+                    respond(&Value::Null, Err(RpcError::new(PARSE_ERROR, err)))?;
+                    continue;
+                }
+            };
+
+            match request.method.as_str() {
+                "run" | "check" | "test" => {
+                    let server = &server;
+                    server
+                        .running
+                        .lock()
+                        .unwrap()
+                        .insert(request.id.to_string(), Vec::new());
+                    scope.spawn(move || server.job(request));
+                }
+                "cancel" => respond(&request.id, server.cancel(request.params))?,
+                "listTests" => respond(&request.id, list_tests(request.params))?,
+                "explain" => respond(&request.id, explain(request.params))?,
+                method => respond(
+                    &request.id,
+                    Err(RpcError::new(
+                        METHOD_NOT_FOUND,
+                        format!("unknown method {:?}", method),
+                    )),
+                )?,
+            }
+        }
+        Ok(0)
+    })
+}
+
+fn respond(id: &Value, result: Result<Value, RpcError>) -> Result<(), CargoPlayError> {
+    events::write_line(&match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": err.code, "message": err.message },
+        }),
+    })
+}
+
+impl Server {
+    fn job(&self, request: Request) -> Result<(), CargoPlayError> {
+        let result = self.execute(&request);
+
+        let key = request.id.to_string();
+        self.running.lock().unwrap().remove(&key);
+        let cancelled = self.cancelled.lock().unwrap().remove(&key);
+
+        respond(
+            &request.id,
+            result.map(|code| json!({ "code": code, "cancelled": cancelled })),
+        )
+    }
+
+    fn execute(&self, request: &Request) -> Result<i32, RpcError> {
+        let target: Target = serde_json::from_value(request.params.clone())
+            .map_err(|err| RpcError::new(INVALID_PARAMS, err))?;
+        let mut options = target.options()?;
+        options.test = request.method == "test";
+        options.check = request.method == "check";
+        let source = target.source()?;
+        if let Some(toolchain) = nightly_toolchain(&options, Some(&source), &[])? {
+            options.toolchain = Some(toolchain);
+        }
+
+        let temp = match &target.file {
+            Some(_) => temp_dir(options.temp_dirname()),
+            None => temp_dir(buffer_dirname(target.name.as_deref().unwrap_or("untitled"))),
+        };
+
+        let lock = self
+            .locks
+            .lock()
+            .unwrap()
+            .entry(temp.clone())
+            .or_default()
+            .clone();
+        let _guard = lock.lock().unwrap();
+
+        let map = self.prepare(&options, &temp, source)?;
+        let notifier = Notifier {
+            server: self,
+            id: &request.id,
+        };
+        Ok(events::run(&options, &temp, &map, &notifier)?)
+    }
+
+    /// Generates the project unless the inputs are the same as in the previous request, in which
+    /// case the files are left alone to keep Cargo's fingerprints fresh.
+    fn prepare(
+        &self,
+        options: &Options,
+        temp: &Path,
+        source: String,
+    ) -> Result<SourceMap, CargoPlayError> {
+        let files: Vec<(String, &Path)> = options
+            .src
+            .iter()
+            .map(|path| (source.clone(), path.as_path()))
+            .collect();
+        let stdin = if files.is_empty() {
+            Some(source.as_str())
+        } else {
+            None
+        };
+
+        let mut hash = sha1::Sha1::new();
+        hash.update(format!("{:?} {} ", options.edition, options.infer));
+        hash.update(source.as_bytes());
+        let fingerprint = bs58::encode(hash.finalize()).into_string();
+
+        let mut projects = self.projects.lock().unwrap();
+        match projects.get(temp) {
+            Some((known, map)) if *known == fingerprint && temp.join("Cargo.toml").exists() => {
+                Ok(map.clone())
+            }
+            _ => {
+                let map = prepare_project(options, temp, stdin, &files)?;
+                projects.insert(temp.to_path_buf(), (fingerprint, map.clone()));
+                Ok(map)
+            }
+        }
+    }
+
+    fn cancel(&self, params: Value) -> Result<Value, RpcError> {
+        let id = params
+            .get("id")
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, "missing request id"))?
+            .to_string();
+
+        // ids of requests that already finished (or never existed) are not recorded, as nothing
+        // would ever remove them again
+        let running = self.running.lock().unwrap();
+        let children = match running.get(&id) {
+            Some(children) => children,
+            None => return Ok(json!({ "cancelled": false })),
+        };
+        self.cancelled.lock().unwrap().insert(id);
+        for child in children {
+            let _ = child.lock().unwrap().kill();
+        }
+
+        Ok(json!({ "cancelled": true }))
+    }
+}
+
+impl Target {
+    fn options(&self) -> Result<Options, RpcError> {
+        let src = match &self.file {
+            Some(file) => vec![file
+                .canonicalize()
+                .map_err(|err| RpcError::new(INVALID_PARAMS, err))?],
+            None if self.source.is_some() => Vec::new(),
+            None => return Err(RpcError::new(INVALID_PARAMS, "expected `file` or `source`")),
+        };
+        let edition = self
+            .edition
+            .as_deref()
+            .map(str::parse)
+            .transpose()
+            .map_err(|err: CargoPlayError| RpcError::new(INVALID_PARAMS, err.chain()))?;
+
+        Ok(Options {
+            src,
+            edition,
+            release: self.release,
+            infer: self.infer,
+            args: self.args.clone(),
+            ..Default::default()
+        })
+    }
+
+    fn source(&self) -> Result<String, CargoPlayError> {
+        match (&self.source, &self.file) {
+            (Some(source), _) => Ok(source.clone()),
+            (None, Some(file)) => Ok(std::fs::read_to_string(file)?),
+            (None, None) => Ok(String::new()),
+        }
+    }
+}
+
+/// Directory of the project of an in-memory buffer. The name comes from the client and may be a
+/// path or a URI, so it is hashed like the paths of files are.
+fn buffer_dirname(name: &str) -> PathBuf {
+    let mut hash = sha1::Sha1::new();
+    hash.update(name.as_bytes());
+    format!(
+        "cargo-play.buffer.{}",
+        bs58::encode(hash.finalize()).into_string()
+    )
+    .into()
+}
+
+fn list_tests(params: Value) -> Result<Value, RpcError> {
+    let target: Target =
+        serde_json::from_value(params).map_err(|err| RpcError::new(INVALID_PARAMS, err))?;
+    let file = syn::parse_file(&target.source()?).map_err(CargoPlayError::from)?;
+
+    let mut tests = Vec::new();
+    collect_tests(&file.items, "", &mut tests);
+    Ok(json!({ "tests": tests }))
+}
+
+fn collect_tests(items: &[syn::Item], prefix: &str, tests: &mut Vec<String>) {
+    for item in items {
+        match item {
+            syn::Item::Fn(function) if function.attrs.iter().any(|a| a.path.is_ident("test")) => {
+                tests.push(format!("{}{}", prefix, function.sig.ident));
+            }
+            syn::Item::Mod(module) => {
+                if let Some((_, items)) = &module.content {
+                    let prefix = format!("{}{}::", prefix, module.ident);
+                    collect_tests(items, &prefix, tests);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn explain(params: Value) -> Result<Value, RpcError> {
+    let code = params
+        .get("code")
+        .and_then(Value::as_str)
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "missing error code"))?;
+
+    let output = Command::new("rustc")
+        .arg("--explain")
+        .arg(code)
+        .output()
+        .map_err(CargoPlayError::from)?;
+    if !output.status.success() {
+        return Err(RpcError::new(
+            INVALID_PARAMS,
+            String::from_utf8_lossy(&output.stderr).trim(),
+        ));
+    }
+
+    Ok(json!({ "explanation": String::from_utf8_lossy(&output.stdout) }))
+}
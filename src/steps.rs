@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::env;
 use std::fs::File;
 use std::io::{Read, Write};
@@ -11,8 +11,13 @@ use log::debug;
 use pathdiff::diff_paths;
 
 use crate::cargo::CargoManifest;
-use crate::errors::CargoPlayError;
-use crate::options::{Options, RustEdition};
+use crate::config::Config;
+use crate::errors::{CargoPlayError, EXIT_INTERNAL};
+use crate::infer::{self, Analysis, BenchHarness, InferredCrate};
+use crate::lint;
+use crate::options::{AsyncRuntime, Options, RustEdition};
+use crate::registry::{self, Registry};
+use crate::runtime;
 
 pub fn read_stdin() -> Result<String, CargoPlayError> {
     let mut buffer = String::new();
@@ -33,20 +38,100 @@ pub fn read_files(inputs: &[PathBuf]) -> Result<Vec<(String, &Path)>, CargoPlayE
         .collect()
 }
 
-pub fn extract_headers(stdin: Option<&str>, sources: &[&str]) -> Vec<String> {
+/// A `//#` header line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    /// The header without its `//#` prefix
+    pub text: String,
+    /// Index of the input it was found in (stdin first, then the files)
+    pub source: usize,
+    /// Line number (1-based)
+    pub line: usize,
+    /// The whole line, prefix included
+    pub raw: String,
+}
+
+pub fn extract_headers(stdin: Option<&str>, sources: &[&str]) -> Vec<Header> {
     stdin
         .iter()
         .chain(sources.iter())
-        .map(|source| -> Vec<String> {
+        .enumerate()
+        .flat_map(|(index, source)| -> Vec<Header> {
             source
                 .lines()
-                .skip_while(|line| line.starts_with("#!") || line.is_empty())
-                .take_while(|line| line.starts_with("//#"))
-                .map(|line| line[3..].trim_start().into())
-                .filter(|s: &String| !s.is_empty())
+                .enumerate()
+                .skip_while(|(_, line)| line.starts_with("#!") || line.is_empty())
+                .take_while(|(_, line)| line.starts_with("//#"))
+                .map(|(number, line)| Header {
+                    text: line[3..].trim_start().into(),
+                    source: index,
+                    line: number + 1,
+                    raw: line.into(),
+                })
+                .filter(|header| !header.text.is_empty())
                 .collect()
         })
-        .flatten()
+        .collect()
+}
+
+/// The edition set by an `//@ edition: 2024` (or `//@ edition 2024`) directive in the comments at
+/// the top of the script. Comments further down, e.g. in the middle of the code, are not read.
+pub fn edition_directive(source: &str) -> Result<Option<RustEdition>, CargoPlayError> {
+    source
+        .lines()
+        .map(str::trim_start)
+        .take_while(|line| {
+            line.is_empty()
+                || line.starts_with("//")
+                || (line.starts_with("#!") && !line.starts_with("#!["))
+        })
+        .filter_map(|line| line.strip_prefix("//@"))
+        .filter_map(|directive| directive.trim_start().strip_prefix("edition"))
+        .map(|value| value.trim_start_matches(|c: char| c == ':' || c.is_whitespace()))
+        .map(|value| value.trim_end().parse())
+        .next()
+        .transpose()
+}
+
+/// The edition to build with: the one given on the command line, else the one of the `entry`
+/// point's directive, else the default. It is checked with rustc, so that any edition the
+/// toolchain knows about can be used.
+pub fn edition(options: &Options, entry: Option<&str>) -> Result<RustEdition, CargoPlayError> {
+    let edition = match (&options.edition, entry) {
+        (Some(edition), _) => edition.clone(),
+        (None, Some(entry)) => edition_directive(entry)?.unwrap_or_default(),
+        (None, None) => RustEdition::default(),
+    };
+
+    let mut rustc = Command::new("rustc");
+    if let Some(toolchain) = options.toolchain.as_ref() {
+        rustc.arg(format!("+{}", toolchain));
+    }
+    let output = rustc
+        .args(["--edition", edition.as_str(), "--print", "crate-name", "-"])
+        .stdin(Stdio::null())
+        .output();
+
+    match output {
+        Ok(output) if !output.status.success() => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let message = stderr.lines().next().unwrap_or_default();
+            Err(CargoPlayError::UnsupportedEdition(
+                edition.into(),
+                message.trim_start_matches("error: ").into(),
+            ))
+        }
+        // without rustc, leave it to Cargo to complain
+        _ => Ok(edition),
+    }
+}
+
+/// Names of the inputs for messages, in the order `Header::source` refers to them.
+pub fn input_names(stdin: Option<&str>, files: &[(String, &Path)]) -> Vec<String> {
+    stdin
+        .map(|_| "<stdin>".to_string())
+        .into_iter()
+        .chain(files.iter().map(|(_, path)| path.display().to_string()))
         .collect()
 }
 
@@ -58,110 +143,499 @@ pub fn temp_dir(name: PathBuf) -> PathBuf {
 }
 
 /// This function ignores the error intentionally.
-pub fn rmtemp(temp: &PathBuf) {
+pub fn rmtemp(temp: &Path) {
     debug!("Cleaning temporary folder at: {:?}", temp);
     let _ = std::fs::remove_dir_all(temp);
 }
 
-pub fn mktemp(temp: &PathBuf) {
+pub fn mktemp(temp: &Path) {
     debug!("Creating temporary building folder at: {:?}", temp);
     if std::fs::create_dir(temp).is_err() {
         debug!("Temporary directory already exists.");
     }
 }
 
-pub fn write_cargo_toml(
-    dir: &PathBuf,
-    name: String,
-    dependencies: Vec<String>,
-    edition: RustEdition,
-    infers: HashSet<String>,
-) -> Result<(), CargoPlayError> {
-    let mut manifest = CargoManifest::new(name, dependencies, edition)?;
+pub fn write_cargo_toml(dir: &Path, project: &GeneratedProject) -> Result<(), CargoPlayError> {
     let mut cargo = File::create(dir.join("Cargo.toml"))?;
 
+    cargo.write_all(&toml::to_vec(&project.manifest).map_err(CargoPlayError::from_serde)?)?;
+
+    Ok(())
+}
+
+/// Writes a manifest whose binary target is the original `entry` file, for editors to load. The
+/// file is left untouched when nothing changed so that they don't reload needlessly.
+pub fn write_ide_manifest(
+    dir: &Path,
+    name: String,
+    headers: &[Header],
+    inputs: &[String],
+    edition: RustEdition,
+    infers: HashMap<String, InferredCrate>,
+    entry: PathBuf,
+) -> Result<PathBuf, CargoPlayError> {
+    let mut manifest = CargoManifest::new(name, headers, inputs, edition)?;
     manifest.add_infers(infers);
+    manifest.set_bin_path(entry);
 
-    cargo.write_all(&toml::to_vec(&manifest).map_err(CargoPlayError::from_serde)?)?;
+    let content = toml::to_string(&manifest).map_err(CargoPlayError::from_serde)?;
+    let path = dir.join("Cargo.toml");
+    if std::fs::read_to_string(&path).ok().as_deref() != Some(content.as_str()) {
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(&path, content)?;
+    }
 
-    Ok(())
+    Ok(path)
+}
+
+/// Analyzes the sources for their dependencies when `--infer` is given.
+pub fn analyze_dependencies(
+    options: &Options,
+    stdin: Option<&str>,
+    sources: &[&str],
+) -> Result<Analysis, CargoPlayError> {
+    if options.infer {
+        infer::analyze_sources(stdin, sources)
+    } else {
+        Ok(Analysis::default())
+    }
+}
+
+/// Resolves the crates of the analysis to dependencies, keyed by the names used in the sources.
+/// Versions are taken from the lockfile of the cached `project` when it has one, so that they stay
+/// the same across runs.
+pub fn resolve_dependencies(
+    options: &Options,
+    project: &Path,
+    analysis: Analysis,
+) -> Result<HashMap<String, InferredCrate>, CargoPlayError> {
+    if analysis.crates.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let locked = registry::locked_versions(&project.join("Cargo.lock"), &options.package_name());
+    let infers = infer::resolve_crates(analysis, &Config::load()?, &Registry::open(), &locked);
+
+    if options.verbose != 0 {
+        for infer in infers.values() {
+            let source = match &infer.version {
+                Some((_, source)) => format!("from {}", source),
+                None => "not in the local registry index".into(),
+            };
+            let features = if infer.features.is_empty() {
+                String::new()
+            } else {
+                format!(
+                    " with features {}",
+                    infer
+                        .features
+                        .iter()
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            };
+            eprintln!(
+                "[cargo-play] inferred {} = \"{}\"{} ({})",
+                infer.package,
+                infer.requirement(),
+                features,
+                source
+            );
+        }
+    }
+
+    Ok(infers)
+}
+
+/// Relates the files of the generated project (relative to the project root) to the inputs they
+/// were copied from. Stdin has no original file.
+#[derive(Debug, Default, Clone)]
+pub struct SourceMap(Vec<(PathBuf, Option<PathBuf>)>);
+
+impl SourceMap {
+    /// Looks up the original of a file in the generated project. `generated` may be either
+    /// relative to the project root or absolute.
+    pub fn original(&self, project: &Path, generated: &Path) -> Option<&Path> {
+        let generated = generated.strip_prefix(project).unwrap_or(generated);
+        self.0
+            .iter()
+            .find(|(copy, _)| copy == generated)
+            .and_then(|(_, original)| original.as_deref())
+    }
+
+    /// The files of the generated project, relative to its root, with their originals.
+    pub fn files(&self) -> impl Iterator<Item = (&Path, Option<&Path>)> {
+        self.0
+            .iter()
+            .map(|(copy, original)| (copy.as_path(), original.as_deref()))
+    }
 }
 
 /// Copy all the passed in sources to the temporary directory. The first in the list will be
 /// treated as main.rs.
 pub fn copy_sources(
-    temp: &PathBuf,
+    temp: &Path,
     stdin: Option<&str>,
     files: &[(String, &Path)],
-) -> Result<(), CargoPlayError> {
+) -> Result<SourceMap, CargoPlayError> {
     let destination = temp.join("src");
     std::fs::create_dir_all(&destination)?;
 
+    let mut map = Vec::new();
     let mut files = files.iter();
     let base: Option<PathBuf> = if let Some(main) = stdin {
         let dst = destination.join("main.rs");
         debug!("Copying stdin => {:?}", dst);
         std::fs::write(dst, main)?;
+        map.push((PathBuf::from("src/main.rs"), None));
         Some(std::env::current_dir()?)
     } else if let Some((main, first)) = files.next() {
         let dst = destination.join("main.rs");
         debug!("Copying {:?} => {:?}", first, dst);
         std::fs::write(dst, main)?;
+        map.push((PathBuf::from("src/main.rs"), Some(first.to_path_buf())));
         first.parent().map(|p| p.to_path_buf())
     } else {
         None
     };
 
     if let Some(base) = &base {
-        files
-            .map(|(source, file)| -> Result<(), CargoPlayError> {
-                let part = diff_paths(file, base)
-                    .ok_or_else(|| CargoPlayError::DiffPathError(file.to_path_buf()))?;
-                let dst = destination.join(part);
-
-                // ensure the parent folder all exists
-                if let Some(parent) = dst.parent() {
-                    let _ = std::fs::create_dir_all(&parent);
-                }
+        for (source, file) in files {
+            let part = diff_paths(file, base)
+                .ok_or_else(|| CargoPlayError::DiffPathError(file.to_path_buf()))?;
+            let dst = destination.join(&part);
+
+            // ensure the parent folder all exists
+            if let Some(parent) = dst.parent() {
+                let _ = std::fs::create_dir_all(parent);
+            }
 
-                debug!("Copying {:?} => {:?}", file, dst);
-                std::fs::write(dst, source)?;
-                Ok(())
+            debug!("Copying {:?} => {:?}", file, dst);
+            std::fs::write(dst, source)?;
+            map.push((Path::new("src").join(part), Some(file.to_path_buf())));
+        }
+    }
+
+    Ok(SourceMap(map))
+}
+
+/// The Cargo project generated for some inputs, before it is written.
+pub struct GeneratedProject {
+    manifest: CargoManifest,
+    /// The entry file, when it had to be rewritten
+    main: Option<String>,
+    /// Names of the inputs, see `input_names`
+    inputs: Vec<String>,
+}
+
+impl GeneratedProject {
+    /// The manifest, commenting where each dependency came from.
+    pub fn annotated_manifest(&self) -> Result<String, CargoPlayError> {
+        self.manifest
+            .to_annotated_string(|infer| match infer.location {
+                Some(location) => {
+                    format!("used at {}:{}", self.inputs[location.source], location.line)
+                }
+                None => "required by the generated entry point".into(),
             })
-            .collect::<Result<Vec<_>, _>>()?;
     }
 
+    /// The inferred dependencies as `//#` header lines.
+    pub fn inferred_headers(&self) -> Vec<String> {
+        self.manifest.inferred_headers()
+    }
+}
+
+/// Computes the manifest (from the headers and inferred dependencies) and the entry point for the
+/// given inputs. `temp` is only read, for its lockfile.
+pub fn generate_project(
+    options: &Options,
+    temp: &Path,
+    stdin: Option<&str>,
+    files: &[(String, &Path)],
+) -> Result<GeneratedProject, CargoPlayError> {
+    let sources: Vec<&str> = files.iter().map(|(source, _)| -> &str { source }).collect();
+    let headers = extract_headers(stdin, &sources);
+    let inputs = input_names(stdin, files);
+
+    let mut analysis = analyze_dependencies(options, stdin, &sources)?;
+    let entry = stdin.or_else(|| sources.first().copied());
+    // syn 1 does not know every newer syntax, and rustc reports real syntax errors better, so a
+    // script it cannot parse is simply not looked at
+    let async_main = entry.and_then(|entry| match infer::async_main(entry) {
+        Ok(main) => main.map(|main| (entry, main)),
+        Err(err) => {
+            debug!("Not looking for async fn main: {}", err);
+            None
+        }
+    });
+    let mut shim_dependency = None;
+    let main = match async_main {
+        Some((entry, main)) => {
+            let runtime = async_runtime(options, &headers, &analysis)?;
+            debug!("Driving async fn main with {:?}", runtime);
+            if let Some((name, features)) = runtime.dependency() {
+                analysis.require(name, features);
+                shim_dependency = Some((name, features));
+            }
+            Some(runtime::add_shim(entry, &main, runtime))
+        }
+        None => None,
+    };
+
+    let bench = match entry {
+        Some(entry) if options.bench => {
+            let harness = bench_harness(entry)?;
+            if harness == BenchHarness::Criterion {
+                analysis.require("criterion", &[]);
+            }
+            Some(harness)
+        }
+        _ => None,
+    };
+
+    let edition = edition(options, entry)?;
+    let mut infers = resolve_dependencies(options, temp, analysis)?;
+    let mut manifest = CargoManifest::new(options.package_name(), &headers, &inputs, edition)?;
+    if let Some(harness) = bench {
+        manifest.set_bench(harness, infers.remove("criterion"));
+    }
+    manifest.add_infers(infers);
+    // the runtime may be declared in the headers without the features the shim needs
+    if let Some((name, features)) = shim_dependency {
+        manifest.enable_features(name, features);
+    }
+
+    Ok(GeneratedProject {
+        manifest,
+        main,
+        inputs,
+    })
+}
+
+/// The harness running the benchmarks of `entry`. A script syn cannot parse is handed to the
+/// libtest harness, so that cargo reports what is wrong with it.
+pub fn bench_harness(entry: &str) -> Result<BenchHarness, CargoPlayError> {
+    match infer::bench_harness(entry) {
+        Ok(harness) => harness.ok_or(CargoPlayError::NoBenchmarks),
+        Err(err) => {
+            debug!("Not looking for benchmarks: {}", err);
+            Ok(BenchHarness::Libtest)
+        }
+    }
+}
+
+/// Generates the Cargo project for the given inputs in `temp`: writes `Cargo.toml` from the
+/// headers (and inferred dependencies) and copies the sources over.
+pub fn prepare_project(
+    options: &Options,
+    temp: &Path,
+    stdin: Option<&str>,
+    files: &[(String, &Path)],
+) -> Result<SourceMap, CargoPlayError> {
+    let project = generate_project(options, temp, stdin, files)?;
+
+    if !options.quiet {
+        let sources: Vec<&str> = files.iter().map(|(source, _)| -> &str { source }).collect();
+        for warning in lint::check(stdin, &sources, &project.inputs, options.lint)? {
+            eprintln!("{}", warning);
+        }
+    }
+
+    if options.clean {
+        rmtemp(temp);
+    }
+    mktemp(temp);
+    write_cargo_toml(temp, &project)?;
+
+    match project.main {
+        Some(main) if stdin.is_some() => copy_sources(temp, Some(&main), files),
+        Some(main) => {
+            let mut files = files.to_vec();
+            files[0].0 = main;
+            copy_sources(temp, None, &files)
+        }
+        None => copy_sources(temp, stdin, files),
+    }
+}
+
+/// Adds `headers` as `//#` lines to the header block at the top of `source`, and writes it to
+/// `path`. The file is left alone when there is nothing to add.
+pub fn write_headers(path: &Path, source: &str, headers: &[String]) -> Result<(), CargoPlayError> {
+    if headers.is_empty() {
+        return Ok(());
+    }
+
+    let lines: Vec<&str> = source.split_inclusive('\n').collect();
+    let (position, end) = header_block(&lines);
+
+    let mut output: String = lines[..end].concat();
+    if !output.is_empty() && !output.ends_with('\n') {
+        output.push('\n');
+    }
+    for header in headers {
+        output.push_str(&format!("//# {}\n", header));
+    }
+    let new_block = end == position;
+    if new_block && lines.get(end).is_some_and(|line| !line.trim().is_empty()) {
+        // keep the new header block apart from the code
+        output.push('\n');
+    }
+    output.push_str(&lines[end..].concat());
+
+    std::fs::write(path, output)?;
     Ok(())
 }
 
-pub fn run_cargo_build(options: &Options, project: &PathBuf) -> Result<ExitStatus, CargoPlayError> {
-    let mut cargo = Command::new("cargo");
+/// Range of the `//#` header block in the `lines` of a source, after the shebang and blank lines
+/// preceding it. The range is empty when there are no headers.
+pub fn header_block(lines: &[&str]) -> (usize, usize) {
+    let position = lines
+        .iter()
+        .position(|line| !(line.starts_with("#!") || line.trim().is_empty()))
+        .unwrap_or(lines.len());
+    let end = position
+        + lines[position..]
+            .iter()
+            .take_while(|line| line.starts_with("//#"))
+            .count();
+    (position, end)
+}
 
-    if let Some(toolchain) = options.toolchain.as_ref() {
-        cargo.arg(format!("+{}", toolchain));
+/// Picks the runtime for an `async fn main`: the one given on the command line, else the one the
+/// script already depends on, else the configured one, else tokio.
+fn async_runtime(
+    options: &Options,
+    headers: &[Header],
+    analysis: &Analysis,
+) -> Result<AsyncRuntime, CargoPlayError> {
+    if let Some(runtime) = options.async_runtime {
+        return Ok(runtime);
+    }
+
+    let declared: Vec<String> = headers
+        .iter()
+        .filter_map(|header| header.text.split(['=', '.']).next())
+        .map(|name| name.trim().trim_matches('"').replace('-', "_"))
+        .collect();
+    let used = AsyncRuntime::CRATES.iter().find(|(name, _)| {
+        declared.iter().any(|declared| declared == name) || analysis.crates.contains(*name)
+    });
+    if let Some((_, runtime)) = used {
+        return Ok(*runtime);
     }
 
-    let subcommand = if options.test {
-        "test"
-    } else if options.check {
-        "check"
-    } else if options.expand {
-        "expand"
-    } else if let Some(mode) = options.mode.as_ref() {
-        mode.as_str()
+    match Config::load()?.async_runtime {
+        Some(runtime) => runtime.parse(),
+        None => Ok(AsyncRuntime::Tokio),
+    }
+}
+
+/// Makes sure rustup has the toolchain selected with `+toolchain` or `--toolchain`. Nothing is
+/// checked when rustup isn't available.
+pub fn check_toolchain(toolchain: &str) -> Result<(), CargoPlayError> {
+    let output = match Command::new("rustup").args(["toolchain", "list"]).output() {
+        Ok(output) if output.status.success() => output,
+        _ => return Ok(()),
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let installed: Vec<&str> = stdout
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .collect();
+
+    // installed toolchains are listed with the host triple, as in `nightly-x86_64-unknown-linux-gnu`,
+    // which must not be confused with a dated one like `nightly-2024-01-01-...`
+    let found = installed.iter().any(|name| {
+        *name == toolchain
+            || name
+                .strip_prefix(toolchain)
+                .and_then(|rest| rest.strip_prefix('-'))
+                .is_some_and(|triple| !triple.starts_with(|c: char| c.is_ascii_digit()))
+    });
+
+    if found {
+        Ok(())
     } else {
-        "run"
+        Err(CargoPlayError::ToolchainNotInstalled(
+            toolchain.to_string(),
+            installed.join(", "),
+        ))
+    }
+}
+
+/// Picks a nightly toolchain when the entry point enables unstable features, see `nightly_for`.
+pub fn nightly_toolchain(
+    options: &Options,
+    stdin: Option<&str>,
+    files: &[(String, &Path)],
+) -> Result<Option<String>, CargoPlayError> {
+    let entry = match stdin.or_else(|| files.first().map(|(source, _)| source.as_str())) {
+        Some(entry) => entry,
+        None => return Ok(None),
     };
+    // like for `async fn main`, rustc gets to report what syn cannot parse
+    let features = infer::unstable_features(entry).unwrap_or_else(|err| {
+        debug!("Not looking for unstable features: {}", err);
+        Vec::new()
+    });
+    if features.is_empty() {
+        return Ok(None);
+    }
+
+    nightly_for(options, &format!("#![feature({})]", features.join(", ")))
+}
+
+/// Picks a nightly toolchain, the configured one or else `nightly`, for something that needs it
+/// (`reason`), unless a toolchain was chosen with `+toolchain` or through rustup (`cargo
+/// +toolchain play`, an override or a toolchain file).
+pub fn nightly_for(options: &Options, reason: &str) -> Result<Option<String>, CargoPlayError> {
+    // rustup sets `RUSTUP_TOOLCHAIN` for every proxied command, telling where the choice comes from
+    // in `RUSTUP_TOOLCHAIN_SOURCE` since 1.28; older versions only set it for `+toolchain`
+    let chosen = match env::var("RUSTUP_TOOLCHAIN_SOURCE") {
+        Ok(source) => source != "default",
+        Err(_) => env::var_os("RUSTUP_TOOLCHAIN").is_some(),
+    };
+    if options.toolchain.is_some() || chosen {
+        return Ok(None);
+    }
+
+    let toolchain = Config::load()?
+        .nightly_toolchain
+        .unwrap_or_else(|| "nightly".into());
+    check_toolchain(&toolchain).map_err(|err| match err {
+        CargoPlayError::ToolchainNotInstalled(..) => {
+            CargoPlayError::NightlyNotInstalled(toolchain.clone(), reason.into())
+        }
+        err => err,
+    })?;
+
+    if !options.quiet {
+        eprintln!(
+            "[cargo-play] using the {} toolchain for {}",
+            toolchain, reason
+        );
+    }
+    Ok(Some(toolchain))
+}
+
+/// Builds a Cargo invocation of `subcommand` on the generated project, carrying the flags shared by
+/// every mode.
+pub fn cargo_subcommand(options: &Options, project: &Path, subcommand: &str) -> Command {
+    let mut cargo = Command::new("cargo");
+
+    if let Some(toolchain) = options.toolchain.as_ref() {
+        cargo.arg(format!("+{}", toolchain));
+    }
 
     cargo
         .arg(subcommand)
         .arg("--manifest-path")
         .arg(project.join("Cargo.toml"));
 
-    if let Some(cargo_option) = options.cargo_option.as_ref() {
-        // FIXME: proper escaping
-        cargo.args(cargo_option.split_ascii_whitespace());
-    }
+    cargo.args(options.cargo_option.iter().flatten());
+    cargo.args(&options.cargo_arg);
 
     if options.release {
         cargo.arg("--release");
@@ -178,12 +652,110 @@ pub fn run_cargo_build(options: &Options, project: &PathBuf) -> Result<ExitStatu
     }
 
     cargo
-        .arg("--")
-        .args(options.args.clone())
+}
+
+/// Builds the Cargo invocation for the selected mode without configuring its stdio.
+pub fn cargo_command(options: &Options, project: &Path) -> Command {
+    let mut cargo = cargo_subcommand(options, project, options.subcommand());
+    cargo.arg("--").args(options.args.clone());
+    cargo
+}
+
+/// Path of the executable `cargo build` produces for the generated project.
+pub fn binary_path(options: &Options, project: &Path) -> PathBuf {
+    let mut bin_path = project.join("target");
+    if options.release {
+        bin_path.push("release");
+    } else {
+        bin_path.push("debug");
+    }
+    bin_path.push(options.package_name() + env::consts::EXE_SUFFIX);
+    bin_path
+}
+
+/// Builds the program of the generated project and returns the path of its executable, or `None`
+/// when the build failed.
+pub fn build_executable(
+    options: &Options,
+    project: &Path,
+) -> Result<Option<PathBuf>, CargoPlayError> {
+    // diagnostics are still rendered on stderr, while stdout tells where the executable is
+    let build = cargo_subcommand(options, project, "build")
+        .arg("--message-format")
+        .arg("json-render-diagnostics")
         .stderr(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .status()
-        .map_err(From::from)
+        .stdout(Stdio::piped())
+        .output()?;
+    if !build.status.success() {
+        return Ok(None);
+    }
+
+    let executable = String::from_utf8_lossy(&build.stdout)
+        .lines()
+        .rev()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .find_map(|message| message["executable"].as_str().map(PathBuf::from))
+        .unwrap_or_else(|| binary_path(options, project));
+    Ok(Some(executable))
+}
+
+/// How building and running the generated project ended.
+pub enum Outcome {
+    /// Cargo failed before the program or the tests could start
+    BuildFailed,
+    /// The program, the tests or the Cargo command of the mode finished with this status
+    Finished(ExitStatus),
+}
+
+/// Builds the generated project, then runs the program or the tests with the arguments given on
+/// the command line. Building first tells a broken build apart from a failing program, and running
+/// through Cargo afterwards gives the program the environment Cargo sets (`CARGO_MANIFEST_DIR`,
+/// `CARGO_PKG_*`, the search path of dynamic libraries).
+pub fn run_project(options: &Options, project: &Path) -> Result<Outcome, CargoPlayError> {
+    let status = |mut command: Command| {
+        command
+            .stderr(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .status()
+    };
+
+    match options.subcommand() {
+        mode @ ("run" | "test") => {
+            let build = if mode == "run" {
+                cargo_subcommand(options, project, "build")
+            } else {
+                let mut build = cargo_subcommand(options, project, "test");
+                build.arg("--no-run");
+                build
+            };
+            let build = status(build)?;
+            if !build.success() {
+                return Ok(Outcome::BuildFailed);
+            }
+            Ok(Outcome::Finished(status(cargo_command(options, project))?))
+        }
+        "check" | "build" => {
+            let build = status(cargo_command(options, project))?;
+            if build.success() {
+                Ok(Outcome::Finished(build))
+            } else {
+                Ok(Outcome::BuildFailed)
+            }
+        }
+        _ => Ok(Outcome::Finished(status(cargo_command(options, project))?)),
+    }
+}
+
+/// Exit code of a finished process, following the shell's `128 + signal` convention for processes
+/// killed by a signal.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(EXIT_INTERNAL)
 }
 
 pub fn copy_project<T: AsRef<Path>, U: AsRef<Path>>(
@@ -199,17 +771,16 @@ pub fn copy_project<T: AsRef<Path>, U: AsRef<Path>>(
     Command::new("cp")
         .arg("-R")
         .arg(from.as_ref())
-        .arg(&to)
+        .arg(to)
         .stderr(Stdio::inherit())
         .stdout(Stdio::inherit())
         .status()
-        .map(|x| {
+        .inspect(|_| {
             // At this point we are certain the `to` path exists
             println!(
                 "Generated project at {}",
                 to.canonicalize().unwrap().display()
             );
-            x
         })
         .map_err(From::from)
 }
@@ -0,0 +1,223 @@
+use std::fs::File;
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::Instant;
+
+use serde::Serialize;
+
+use crate::errors::{CargoPlayError, EXIT_BUILD};
+use crate::options::Options;
+use crate::steps::*;
+
+#[derive(Debug, Serialize)]
+struct Run {
+    /// Wall time in seconds
+    time: f64,
+    /// Peak resident set size in KiB, when the platform reports it
+    max_rss: Option<u64>,
+}
+
+/// Summary of the wall times of the runs, in seconds.
+#[derive(Debug, PartialEq, Serialize)]
+struct Stats {
+    mean: f64,
+    stddev: f64,
+    median: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Stats {
+    fn new(times: &[f64]) -> Self {
+        let count = times.len() as f64;
+        let mean = times.iter().sum::<f64>() / count;
+        let variance = if times.len() > 1 {
+            times.iter().map(|time| (time - mean).powi(2)).sum::<f64>() / (count - 1.0)
+        } else {
+            0.0
+        };
+
+        let mut sorted = times.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let middle = sorted.len() / 2;
+        let median = if sorted.len().is_multiple_of(2) {
+            (sorted[middle - 1] + sorted[middle]) / 2.0
+        } else {
+            sorted[middle]
+        };
+
+        Stats {
+            mean,
+            stddev: variance.sqrt(),
+            median,
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    args: &'a [String],
+    warmup: usize,
+    #[serde(flatten)]
+    stats: Stats,
+    /// Largest peak resident set size of the runs in KiB
+    max_rss: Option<u64>,
+    runs: &'a [Run],
+}
+
+/// Builds the program of the generated project in release mode, then runs it `runs` times after
+/// the warm-up runs, with its output discarded, and reports how long the runs took and how much
+/// memory they used.
+pub fn time_runs(options: &Options, project: &Path, runs: usize) -> Result<i32, CargoPlayError> {
+    let options = Options {
+        release: true,
+        ..options.clone()
+    };
+    let executable = match build_executable(&options, project)? {
+        Some(executable) => executable,
+        None => return Ok(EXIT_BUILD),
+    };
+
+    let warmup = options.warmup.unwrap_or(1);
+    if !options.quiet {
+        eprintln!(
+            "[cargo-play] timing {} run(s) after {} warm-up run(s)",
+            runs, warmup
+        );
+    }
+
+    let mut results = Vec::with_capacity(runs);
+    for index in 0..warmup + runs {
+        let started = Instant::now();
+        let child = Command::new(&executable)
+            .args(&options.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let (status, max_rss) = wait(child)?;
+        let time = started.elapsed().as_secs_f64();
+
+        if !status.success() {
+            eprintln!(
+                "[cargo-play] run {} failed with exit code {}, run the program without \
+                 `--time-runs` to see its output",
+                index + 1,
+                exit_code(status)
+            );
+            return Ok(exit_code(status));
+        }
+        if index >= warmup {
+            results.push(Run { time, max_rss });
+        }
+    }
+
+    let times: Vec<f64> = results.iter().map(|run| run.time).collect();
+    let report = Report {
+        args: &options.args,
+        warmup,
+        stats: Stats::new(&times),
+        max_rss: results.iter().filter_map(|run| run.max_rss).max(),
+        runs: &results,
+    };
+    print_report(&report);
+
+    if let Some(path) = &options.export_json {
+        serde_json::to_writer_pretty(File::create(path)?, &report)
+            .map_err(CargoPlayError::from_serde)?;
+    }
+
+    Ok(0)
+}
+
+fn print_report(report: &Report) {
+    let stats = &report.stats;
+    println!(
+        "Time (mean ± σ):     {} ± {}",
+        duration(stats.mean),
+        duration(stats.stddev)
+    );
+    println!("Median:              {}", duration(stats.median));
+    println!(
+        "Range (min … max):   {} … {}",
+        duration(stats.min),
+        duration(stats.max)
+    );
+    if let Some(max_rss) = report.max_rss {
+        println!("Peak memory:         {:.1} MiB", max_rss as f64 / 1024.0);
+    }
+    println!(
+        "                     {} run(s) after {} warm-up run(s)",
+        report.runs.len(),
+        report.warmup
+    );
+}
+
+/// A duration given in seconds, in the unit that suits it.
+fn duration(seconds: f64) -> String {
+    if seconds >= 1.0 {
+        format!("{:.3} s", seconds)
+    } else if seconds >= 1e-3 {
+        format!("{:.3} ms", seconds * 1e3)
+    } else {
+        format!("{:.1} µs", seconds * 1e6)
+    }
+}
+
+/// Waits for `child` and returns its peak resident set size in KiB, taken from the `getrusage`
+/// statistics `wait4` reports for that single process. On Linux, it is never lower than the
+/// memory cargo-play itself used when spawning the child.
+#[cfg(unix)]
+fn wait(child: Child) -> Result<(ExitStatus, Option<u64>), CargoPlayError> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut status = 0;
+    // SAFETY: `rusage` is plain data, valid when zeroed, which `wait4` fills in.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: the child hasn't been waited for yet, and both pointers are valid.
+        let pid = unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, 0, &mut usage) };
+        if pid != -1 {
+            break;
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err.into());
+        }
+    }
+
+    // Linux reports kilobytes, macOS bytes
+    let max_rss = if cfg!(target_os = "macos") {
+        usage.ru_maxrss / 1024
+    } else {
+        usage.ru_maxrss
+    };
+    Ok((ExitStatus::from_raw(status), Some(max_rss as u64)))
+}
+
+#[cfg(not(unix))]
+fn wait(mut child: Child) -> Result<(ExitStatus, Option<u64>), CargoPlayError> {
+    Ok((child.wait()?, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        assert_eq!(
+            Stats::new(&[3.0, 1.0, 2.0, 6.0]),
+            Stats {
+                mean: 3.0,
+                stddev: (14.0f64 / 3.0).sqrt(),
+                median: 2.5,
+                min: 1.0,
+                max: 6.0,
+            }
+        );
+        assert_eq!(Stats::new(&[2.0]).stddev, 0.0);
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Stdio};
+use std::time::{Duration, SystemTime};
+
+use log::debug;
+use proc_macro2::{TokenStream, TokenTree};
+use quote::ToTokens;
+
+use crate::errors::CargoPlayError;
+use crate::options::Options;
+use crate::steps::*;
+
+/// Interval between two scans of the watched files.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Files have to stay untouched for this long before a rebuild starts, so that editors writing a
+/// file in several steps (or saving several files at once) trigger a single run.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+pub type Snapshot = BTreeMap<PathBuf, Option<SystemTime>>;
+
+/// Rebuilds and reruns the configured mode every time one of the inputs changes. The loop only
+/// ends with an error, e.g. when the inputs can no longer be read or the build cannot be spawned.
+/// Ctrl-C is not handled here: it reaches the whole process group, so the running program stops
+/// together with cargo-play.
+pub fn watch(options: &Options) -> Result<i32, CargoPlayError> {
+    let temp = temp_dir(options.temp_dirname());
+    let mut watched = options.src.clone();
+
+    loop {
+        if options.clear {
+            print!("\x1b[2J\x1b[H");
+            std::io::stdout().flush()?;
+        }
+
+        let files = read_files(&options.src)?;
+        watched = watched_files(&files).unwrap_or(watched);
+        debug!("Watching {:?}", watched);
+        let snapshot = snapshot(&watched);
+
+        let options = &match nightly_toolchain(options, None, &files)? {
+            Some(toolchain) => Options {
+                toolchain: Some(toolchain),
+                ..options.clone()
+            },
+            None => options.clone(),
+        };
+
+        let mut child = match prepare_project(options, &temp, None, &files) {
+            Ok(_) => spawn(options, &temp)?,
+            Err(err) => {
+                eprintln!("error: {}", err.chain());
+                None
+            }
+        };
+
+        wait_for_change(&watched, snapshot, || {
+            if let Some(status) = child.as_mut().map(Child::try_wait).transpose()?.flatten() {
+                eprintln!("[cargo-play] {}, waiting for changes...", status);
+                child = None;
+            }
+            Ok(())
+        })?;
+
+        if let Some(mut child) = child {
+            debug!("Killing previous run");
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Starts the configured mode. `run` is split into a build and a direct spawn of the binary so
+/// that killing the child really stops the program rather than only Cargo.
+fn spawn(options: &Options, temp: &Path) -> Result<Option<Child>, CargoPlayError> {
+    if options.subcommand() != "run" {
+        return Ok(Some(cargo_command(options, temp).spawn()?));
+    }
+
+    let build = cargo_subcommand(options, temp, "build")
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .status()?;
+    if !build.success() {
+        eprintln!("[cargo-play] build failed, waiting for changes...");
+        return Ok(None);
+    }
+
+    Ok(Some(
+        std::process::Command::new(binary_path(options, temp))
+            .args(&options.args)
+            .spawn()?,
+    ))
+}
+
+/// Blocks until one of `files` differs from `snapshot` and then stays unchanged for the debounce
+/// period. `tick` runs on every poll.
+pub fn wait_for_change<F: FnMut() -> Result<(), CargoPlayError>>(
+    files: &[PathBuf],
+    snapshot: Snapshot,
+    mut tick: F,
+) -> Result<(), CargoPlayError> {
+    while snapshot == self::snapshot(files) {
+        std::thread::sleep(POLL_INTERVAL);
+        tick()?;
+    }
+
+    let mut current = self::snapshot(files);
+    loop {
+        std::thread::sleep(DEBOUNCE);
+        let next = self::snapshot(files);
+        if next == current {
+            return Ok(());
+        }
+        current = next;
+    }
+}
+
+pub fn snapshot(files: &[PathBuf]) -> Snapshot {
+    files
+        .iter()
+        .map(|file| {
+            let modified = std::fs::metadata(file).and_then(|m| m.modified()).ok();
+            (file.clone(), modified)
+        })
+        .collect()
+}
+
+/// Collects the input files together with every module file they declare and every file pulled
+/// in with `include_str!`/`include_bytes!`.
+pub fn watched_files(files: &[(String, &Path)]) -> Result<Vec<PathBuf>, CargoPlayError> {
+    let mut watched: Vec<PathBuf> = Vec::new();
+    let mut pending: Vec<(PathBuf, bool)> = files
+        .iter()
+        .enumerate()
+        .map(|(index, (_, path))| (path.to_path_buf(), index == 0))
+        .collect();
+
+    while let Some((file, is_root)) = pending.pop() {
+        if watched.contains(&file) {
+            continue;
+        }
+        watched.push(file.clone());
+
+        if file.extension() != Some(OsStr::new("rs")) || !file.exists() {
+            continue;
+        }
+
+        let source = std::fs::read_to_string(&file)?;
+        let tokens = syn::parse_file(&source)?.into_token_stream();
+        let base = file.parent().map(Path::to_path_buf).unwrap_or_default();
+        let module_dir = match file.file_stem().and_then(|s| s.to_str()) {
+            Some("mod") | Some("main") | Some("lib") => base.clone(),
+            _ if is_root => base.clone(),
+            Some(stem) => base.join(stem),
+            None => base.clone(),
+        };
+
+        let mut references = Vec::new();
+        collect_references(tokens, &mut references);
+        for reference in references {
+            match reference {
+                Reference::Module(name) => {
+                    let flat = module_dir.join(format!("{}.rs", name));
+                    let nested = module_dir.join(&name).join("mod.rs");
+                    let found = if nested.exists() && !flat.exists() {
+                        nested
+                    } else {
+                        flat
+                    };
+                    pending.push((found, false));
+                }
+                Reference::Include(path) => pending.push((base.join(path), false)),
+            }
+        }
+    }
+
+    Ok(watched)
+}
+
+enum Reference {
+    Module(String),
+    Include(String),
+}
+
+fn collect_references(input: TokenStream, references: &mut Vec<Reference>) {
+    use TokenTree as tt;
+
+    let tokens: Vec<TokenTree> = input.into_iter().collect();
+    for (index, token) in tokens.iter().enumerate() {
+        match (token, tokens.get(index + 1), tokens.get(index + 2)) {
+            (tt::Ident(keyword), Some(tt::Ident(name)), Some(tt::Punct(semi)))
+                if keyword == "mod" && semi.as_char() == ';' =>
+            {
+                references.push(Reference::Module(name.to_string()));
+            }
+            (tt::Ident(name), Some(tt::Punct(bang)), Some(tt::Group(args)))
+                if (name == "include_str" || name == "include_bytes") && bang.as_char() == '!' =>
+            {
+                if let Some(tt::Literal(literal)) = args.stream().into_iter().next() {
+                    if let Ok(syn::Lit::Str(path)) =
+                        syn::parse_str::<syn::Lit>(&literal.to_string())
+                    {
+                        references.push(Reference::Include(path.value()));
+                    }
+                }
+            }
+            (tt::Group(group), _, _) => collect_references(group.stream(), references),
+            _ => {}
+        }
+    }
+}
@@ -116,7 +116,7 @@ impl From<std::process::Output> for StringOutput {
 #[test]
 fn basic() -> Result<()> {
     let rt = TestRuntime::new()?;
-    let output = rt.run(&["fixtures/hello.rs"])?;
+    let output = rt.run(["fixtures/hello.rs"])?;
 
     assert_eq!(output.status.code().unwrap(), 0);
     assert_eq!(output.stdout, "Hello World!\n");
@@ -136,13 +136,13 @@ fn clean() -> Result<()> {
     }
 
     println!("{:?}", path);
-    let _ = rt.run(&["fixtures/hello.rs"])?;
+    let _ = rt.run(["fixtures/hello.rs"])?;
     assert!(path.exists());
 
     std::fs::write(&canary, "I_AM_CANARY")?;
 
     assert!(canary.exists());
-    let _ = rt.run(&["--clean", "fixtures/hello.rs"])?;
+    let _ = rt.run(["--clean", "fixtures/hello.rs"])?;
     assert!(!canary.exists());
 
     Ok(())
@@ -153,19 +153,35 @@ fn edition() -> Result<()> {
     let rt = TestRuntime::new()?;
 
     // default edition is 2021
-    let output = rt.run(&["fixtures/edition.rs"])?;
+    let output = rt.run(["fixtures/edition.rs"])?;
     assert_ne!(output.status.code().unwrap(), 0);
 
-    let output = rt.run(&["--edition", "2021", "fixtures/edition.rs"])?;
+    let output = rt.run(["--edition", "2021", "fixtures/edition.rs"])?;
     assert_ne!(output.status.code().unwrap(), 0);
 
-    let output = rt.run(&["--edition", "2018", "fixtures/edition.rs"])?;
+    let output = rt.run(["--edition", "2018", "fixtures/edition.rs"])?;
     assert_ne!(output.status.code().unwrap(), 0);
 
     // it should pass in 2015
-    let output = rt.run(&["--edition", "2015", "fixtures/edition.rs"])?;
+    let output = rt.run(["--edition", "2015", "fixtures/edition.rs"])?;
     assert_eq!(output.status.code().unwrap(), 0);
 
+    let output = rt.run(["--edition", "2024", "fixtures/hello.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+
+    let output = rt.run(["--edition", "2099", "fixtures/hello.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 64);
+    assert!(output
+        .stderr
+        .contains("Edition 2099 isn't supported by the toolchain"));
+
+    // the script's own directive, unless given on the command line
+    let output = rt.run(["fixtures/edition-directive.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+
+    let output = rt.run(["--edition", "2018", "fixtures/edition-directive.rs"])?;
+    assert_ne!(output.status.code().unwrap(), 0);
+
     Ok(())
 }
 
@@ -176,7 +192,7 @@ fn debug_mode() -> Result<()> {
     let opt = Options::with_files(vec!["fixtures/hello.rs"]);
     let path = rt.temp_dir(opt.temp_dirname());
 
-    let _ = rt.run(&["fixtures/hello.rs"])?;
+    let _ = rt.run(["fixtures/hello.rs"])?;
     assert!(path.join("target").join("debug").exists());
     assert!(!path.join("target").join("release").exists());
 
@@ -190,7 +206,7 @@ fn release_mode() -> Result<()> {
     let opt = Options::with_files(vec!["fixtures/hello.rs"]);
     let path = rt.temp_dir(opt.temp_dirname());
 
-    let _ = rt.run(&["--release", "fixtures/hello.rs"])?;
+    let _ = rt.run(["--release", "fixtures/hello.rs"])?;
     assert!(!path.join("target").join("debug").exists());
     assert!(path.join("target").join("release").exists());
 
@@ -200,15 +216,51 @@ fn release_mode() -> Result<()> {
 #[test]
 fn quiet_mode() -> Result<()> {
     let rt = TestRuntime::new()?;
-    let output = rt.run(&["--quiet", "fixtures/hello.rs"])?;
+    let output = rt.run(["--quiet", "fixtures/hello.rs"])?;
     assert!(!output.stderr.contains("Running"));
     Ok(())
 }
 
+#[test]
+fn cargo_environment() -> Result<()> {
+    let rt = TestRuntime::new()?;
+    let output = rt.run(["fixtures/env.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert_eq!(output.stdout, "true\n");
+    assert!(output.stderr.contains("Running"));
+    Ok(())
+}
+
+#[test]
+fn matrix() -> Result<()> {
+    let rt = TestRuntime::new()?;
+
+    let output = rt.run(["fixtures/edition.rs", "--matrix", "edition=2015,2021"])?;
+    assert_eq!(output.status.code().unwrap(), 1);
+    let lines: Vec<&str> = output.stdout.lines().collect();
+    assert_eq!(
+        lines[1..4],
+        ["edition  exit", "2015     0", "2021     build failed"]
+    );
+    assert!(output
+        .stdout
+        .contains("--- 2015 (stdout)\n+++ 2021 (stdout)\n"));
+    assert!(output.stdout.contains("\n-hello 1\n"));
+
+    // the path after `--matrix` is still an input, not another axis
+    let output = rt.run(["--matrix", "edition=2018,2021", "fixtures/hello.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert!(output
+        .stdout
+        .contains("every combination printed the same output"));
+
+    Ok(())
+}
+
 #[test]
 fn verbose_mode() -> Result<()> {
     let rt = TestRuntime::new()?;
-    let output = rt.run(&["-v", "fixtures/hello.rs"])?;
+    let output = rt.run(["-v", "fixtures/hello.rs"])?;
     assert!(output.stderr.contains("rustc"));
     Ok(())
 }
@@ -220,7 +272,7 @@ fn cargo_option() -> Result<()> {
     let opt = Options::with_files(vec!["fixtures/hello.rs"]);
     let path = rt.temp_dir(opt.temp_dirname());
 
-    let _ = rt.run(&["--cargo-option=--release", "fixtures/hello.rs"])?;
+    let _ = rt.run(["--cargo-option=--release", "fixtures/hello.rs"])?;
 
     assert!(!path.join("target").join("debug").exists());
     assert!(path.join("target").join("release").exists());
@@ -228,21 +280,90 @@ fn cargo_option() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn cargo_option_quoting() -> Result<()> {
+    let rt = TestRuntime::new()?;
+    let target = rt.temp_dir("targets");
+
+    // quoted and escaped spaces, in repeated options
+    let output = rt.run([
+        "--cargo-option",
+        &format!("--target-dir '{}/single quoted'", target.display()),
+        "--cargo-option=--release",
+        "fixtures/hello.rs",
+    ])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert!(target.join("single quoted").join("release").exists());
+
+    let output = rt.run([
+        "--cargo-option",
+        &format!("--target-dir \"{}/double quoted\"", target.display()),
+        "fixtures/hello.rs",
+    ])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert!(target.join("double quoted").join("debug").exists());
+
+    let output = rt.run([
+        "--cargo-option",
+        &format!("--target-dir {}/back\\ slash", target.display()),
+        "fixtures/hello.rs",
+    ])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert!(target.join("back slash").join("debug").exists());
+
+    // passed through verbatim, quotes included
+    let output = rt.run([
+        "--cargo-arg",
+        "--target-dir",
+        "--cargo-arg",
+        &format!("{}/'verbatim'", target.display()),
+        "fixtures/hello.rs",
+    ])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert!(target.join("'verbatim'").join("debug").exists());
+
+    let output = rt.run(["--cargo-option", "--target-dir 'open", "fixtures/hello.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 64);
+    assert!(output.stderr.contains("missing closing quote"));
+
+    Ok(())
+}
+
 #[test]
 fn program_args() -> Result<()> {
     let rt = TestRuntime::new()?;
 
-    let output = rt.run(&["fixtures/args.rs", "--", "test"])?;
+    let output = rt.run(["fixtures/args.rs", "--", "test"])?;
     assert_eq!(output.stdout, "test\n");
 
     Ok(())
 }
 
+#[test]
+fn toolchain() -> Result<()> {
+    let rt = TestRuntime::new()?;
+
+    // only the first argument selects a toolchain
+    let output = rt.run(["fixtures/args.rs", "--", "+5", "+7"])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert_eq!(output.stdout, "+5\n");
+
+    if Command::new("rustup").arg("--version").output().is_ok() {
+        let output = rt.run(["+no-such-toolchain", "fixtures/hello.rs"])?;
+        assert_eq!(output.status.code().unwrap(), 64);
+        assert!(output
+            .stderr
+            .contains("rustup toolchain install no-such-toolchain"));
+    }
+
+    Ok(())
+}
+
 #[test]
 fn external_crate() -> Result<()> {
     let rt = TestRuntime::new()?;
 
-    let output = rt.run(&["fixtures/bitflags.rs"])?;
+    let output = rt.run(["fixtures/bitflags.rs"])?;
     assert_eq!(output.status.code().unwrap(), 0);
 
     Ok(())
@@ -251,17 +372,289 @@ fn external_crate() -> Result<()> {
 #[test]
 fn simple_infer() -> Result<()> {
     let rt = TestRuntime::new()?;
-    let output = rt.run(&["--infer", "fixtures/infer.rs"])?;
+    let output = rt.run(["--infer", "fixtures/infer.rs"])?;
     assert_eq!(output.status.code().unwrap(), 0);
 
     Ok(())
 }
 
 #[test]
-fn infer_failure() -> Result<()> {
+fn infer_package_name() -> Result<()> {
     let rt = TestRuntime::new()?;
-    let output = rt.run(&["--infer", "fixtures/infer-failure.rs"])?;
+    let output = rt.run(["--infer", "fixtures/infer-package-name.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn infer_features() -> Result<()> {
+    let rt = TestRuntime::new()?;
+    let output = rt.run(["--infer", "fixtures/infer-features.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert_eq!(output.stdout, "{\"x\":1,\"y\":2}\n");
+
+    Ok(())
+}
+
+#[test]
+fn async_main_builtin() -> Result<()> {
+    let rt = TestRuntime::new()?;
+    let output = rt.run(["--async-runtime", "builtin", "fixtures/async-main.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert_eq!(output.stdout, "The answer is 42\n");
+
+    Ok(())
+}
+
+#[test]
+fn async_main_tokio_header() -> Result<()> {
+    let rt = TestRuntime::new()?;
+    let output = rt.run(["--print-manifest", "fixtures/async-tokio.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert!(output
+        .stdout
+        .contains("[dependencies.tokio] # from //# headers\nfeatures = [\"rt-multi-thread\"]"));
+
+    Ok(())
+}
+
+#[test]
+fn syntax_unknown_to_syn() -> Result<()> {
+    let rt = TestRuntime::new()?;
+    let output = rt.run(["fixtures/new-syntax.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert_eq!(output.stdout, "Hello World!\n");
+
+    Ok(())
+}
+
+#[test]
+fn print_manifest() -> Result<()> {
+    let rt = TestRuntime::new()?;
+    let output = rt.run(["--infer", "--print-manifest", "fixtures/infer-features.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    let serde = output
+        .stdout
+        .lines()
+        .find(|line| line.starts_with("[dependencies.serde]"))
+        .unwrap();
+    assert!(serde.contains("# inferred: used at "));
+    assert!(serde.ends_with("infer-features.rs:1"));
+    assert!(output.stdout.contains("features = [\"derive\"]"));
+
+    let output = rt.run(["--infer", "--print-manifest", "fixtures/infer-override.rs"])?;
+    assert!(output
+        .stdout
+        .contains("regex-syntax = \"*\" # from //# headers"));
+
+    Ok(())
+}
+
+#[test]
+fn write_headers() -> Result<()> {
+    let rt = TestRuntime::new()?;
+    let script = rt.temp_dir("script.rs");
+    std::fs::copy("fixtures/infer-features.rs", &script)?;
+
+    let output = rt.run([
+        OsStr::new("--infer"),
+        OsStr::new("--write-headers"),
+        script.as_os_str(),
+    ])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+
+    let content = std::fs::read_to_string(&script)?;
+    let mut lines = content.lines();
+    assert!(lines
+        .next()
+        .unwrap()
+        .starts_with("//# serde = { version = \"^1."));
+    assert!(lines.next().unwrap().starts_with("//# serde_json = \"^1."));
+    assert_eq!(lines.next(), Some(""));
+    assert_eq!(lines.next(), Some("use serde::Serialize;"));
+
+    // everything is declared now, so there is nothing left to add
+    let output = rt.run([
+        OsStr::new("--infer"),
+        OsStr::new("--write-headers"),
+        script.as_os_str(),
+    ])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert_eq!(std::fs::read_to_string(&script)?, content);
+
+    Ok(())
+}
+
+#[test]
+fn bench() -> Result<()> {
+    let rt = TestRuntime::new()?;
+
+    let output = rt.run(["--bench", "--print-manifest", "fixtures/criterion.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert!(output.stdout.contains("autobins = false\n"));
+    assert!(output.stdout.contains("[[bench]]\n"));
+    assert!(output.stdout.contains("harness = false\n"));
+    assert!(output.stdout.contains("[dev-dependencies]\ncriterion = "));
+
+    let output = rt.run(["--bench", "fixtures/hello.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 65);
+    assert!(output.stderr.contains("No benchmarks found"));
+
+    let installed = match Command::new("rustup").args(["toolchain", "list"]).output() {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).into_owned(),
+        Err(_) => return Ok(()),
+    };
+    if !installed.lines().any(|line| line.starts_with("nightly-")) {
+        return Ok(());
+    }
+
+    let output = rt.run([
+        "+nightly",
+        "--bench",
+        "fixtures/bench.rs",
+        "--baseline",
+        "old",
+    ])?;
+    assert_eq!(output.status.code().unwrap(), 64);
+
+    let output = rt.run([
+        "+nightly",
+        "--bench",
+        "fixtures/bench.rs",
+        "--save-baseline",
+        "old",
+    ])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert!(output.stdout.contains("test bench_add ... bench:"));
+
+    let output = rt.run([
+        "+nightly",
+        "--bench",
+        "fixtures/bench.rs",
+        "--baseline",
+        "old",
+    ])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert!(output.stdout.contains("benchmark  baseline"));
+    assert!(output.stdout.contains("\nbench_add  "));
+
+    Ok(())
+}
+
+#[test]
+fn time_runs() -> Result<()> {
+    let rt = TestRuntime::new()?;
+    let report = rt.temp_dir("timings.json");
+
+    let output = rt.run([
+        OsStr::new("--time-runs"),
+        OsStr::new("3"),
+        OsStr::new("--warmup"),
+        OsStr::new("2"),
+        OsStr::new("--export-json"),
+        report.as_os_str(),
+        OsStr::new("fixtures/args.rs"),
+        OsStr::new("--"),
+        OsStr::new("input"),
+    ])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert!(output.stdout.contains("Time (mean ± σ):"));
+    assert!(output.stdout.contains("3 run(s) after 2 warm-up run(s)"));
+    // the program's own output is discarded
+    assert!(!output.stdout.contains("input"));
+
+    let report: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&report)?)?;
+    assert_eq!(report["runs"].as_array().map(Vec::len), Some(3));
+    assert_eq!(report["args"][0], "input");
+    assert!(report["min"].as_f64().unwrap() <= report["median"].as_f64().unwrap());
+
+    let output = rt.run(["--time-runs", "3", "fixtures/abort.rs"])?;
     assert_ne!(output.status.code().unwrap(), 0);
+    assert!(output.stderr.contains("run 1 failed"));
+
+    let output = rt.run(["--time-runs", "0", "fixtures/hello.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 64);
+
+    Ok(())
+}
+
+#[test]
+fn fmt() -> Result<()> {
+    let rt = TestRuntime::new()?;
+    let script = rt.temp_dir("fmt.rs");
+    std::fs::copy("fixtures/fmt.rs", &script)?;
+
+    let output = rt.run([OsStr::new("fmt"), OsStr::new("--check"), script.as_os_str()])?;
+    assert_eq!(output.status.code().unwrap(), 1);
+    assert!(output.stdout.contains("+//# rand  = \"0.8\"\n"));
+    assert_eq!(std::fs::read("fixtures/fmt.rs")?, std::fs::read(&script)?);
+
+    let output = rt.run([OsStr::new("fmt"), script.as_os_str()])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert_eq!(
+        std::fs::read_to_string(&script)?,
+        r#"#!/usr/bin/env cargo-play
+//# rand  = "0.8"
+//# serde = { version = "1", features = ["derive"] }
+
+use std::collections::HashMap;
+fn main() {
+    let mut map = HashMap::new();
+    map.insert(1, 2);
+    println!("{:?}", map);
+}
+"#
+    );
+
+    let output = rt.run([OsStr::new("fmt"), OsStr::new("--check"), script.as_os_str()])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+
+    // the manifest would only keep the second declaration, so there is no safe way to merge them
+    let conflicting = "//# serde = \"1\"\n//# serde = { features = [\"derive\"] }\nfn main() {}\n";
+    std::fs::write(&script, conflicting)?;
+    let output = rt.run([OsStr::new("fmt"), script.as_os_str()])?;
+    assert_eq!(output.status.code().unwrap(), 65);
+    assert!(output
+        .stderr
+        .contains("`serde` is declared again with a different requirement"));
+    assert_eq!(std::fs::read_to_string(&script)?, conflicting);
+
+    Ok(())
+}
+
+#[test]
+fn fix() -> Result<()> {
+    let rt = TestRuntime::new()?;
+    let script = rt.temp_dir("fix.rs");
+    std::fs::copy("fixtures/fix.rs", &script)?;
+    let run = |mode: &str| {
+        rt.run([
+            OsStr::new(mode),
+            OsStr::new("--async-runtime"),
+            OsStr::new("builtin"),
+            script.as_os_str(),
+        ])
+    };
+
+    // `async fn main` is written back under its own name, without the entry point driving it
+    let output = run("--fix")?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    let content = std::fs::read_to_string(&script)?;
+    assert!(content.contains("    let count = double(1);\n"));
+    assert!(content.contains("    return x * 2;\n"));
+    assert!(content.ends_with(
+        "async fn main() {\n    let count = double(1);\n    println!(\"{}\", count);\n}\n"
+    ));
+
+    let output = run("--clippy")?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    let content = std::fs::read_to_string(&script)?;
+    assert!(content.starts_with("fn double(x: u32) -> u32 {\n    x * 2\n}\n"));
+
+    let output = run("--fix")?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert!(output.stderr.contains("Nothing to fix"));
 
     Ok(())
 }
@@ -269,17 +662,54 @@ fn infer_failure() -> Result<()> {
 #[test]
 fn infer_override() -> Result<()> {
     let rt = TestRuntime::new()?;
-    let output = rt.run(&["--infer", "fixtures/infer-override.rs"])?;
+    let output = rt.run(["--infer", "fixtures/infer-override.rs"])?;
     assert_eq!(output.status.code().unwrap(), 0);
 
     Ok(())
 }
 
+#[test]
+fn infer_local_module() -> Result<()> {
+    let rt = TestRuntime::new()?;
+    let output = rt.run([
+        "--infer",
+        "fixtures/infer-local/entry.rs",
+        "fixtures/infer-local/utils.rs",
+    ])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert_eq!(output.stdout, "Hello World!\nHello World!\n");
+
+    Ok(())
+}
+
+#[test]
+fn infer_local_items() -> Result<()> {
+    let rt = TestRuntime::new()?;
+    let output = rt.run(["--infer", "fixtures/infer-local-items.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn infer_multi() -> Result<()> {
+    let rt = TestRuntime::new()?;
+    let output = rt.run([
+        "--infer",
+        "fixtures/multi/entry.rs",
+        "fixtures/multi/hello.rs",
+    ])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert_eq!(output.stdout, "Hello World\n");
+
+    Ok(())
+}
+
 /// See https://github.com/fanzeyi/cargo-play/pull/13 for details
 #[test]
 fn dtoa_test() -> Result<()> {
     let rt = TestRuntime::new()?;
-    let output = rt.run(&["fixtures/dtoa.rs"])?;
+    let output = rt.run(["fixtures/dtoa.rs"])?;
     assert_eq!(dbg!(output).status.code().unwrap(), 0);
 
     Ok(())
@@ -288,7 +718,7 @@ fn dtoa_test() -> Result<()> {
 #[test]
 fn test_mode_test() -> Result<()> {
     let rt = TestRuntime::new()?;
-    let output = rt.run(&["--test", "fixtures/tests.rs"])?;
+    let output = rt.run(["--test", "fixtures/tests.rs"])?;
     println!("{}", output.stderr);
     assert_eq!(output.status.code().unwrap(), 0);
 
@@ -299,7 +729,7 @@ fn test_mode_test() -> Result<()> {
 fn stdin_with_hello() -> Result<()> {
     let rt = TestRuntime::new()?;
     let mut ps = {
-        let mut p = rt.run_with_stdin(&["--stdin", "mod_hello.rs"]);
+        let mut p = rt.run_with_stdin(["--stdin", "mod_hello.rs"]);
         p.current_dir(std::fs::canonicalize("fixtures")?);
         p.spawn()?
     };
@@ -318,3 +748,405 @@ fn stdin_with_hello() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn run_all() -> Result<()> {
+    let rt = TestRuntime::new()?;
+    let report = rt.temp_dir("report.json");
+    let junit = rt.temp_dir("report.xml");
+
+    let output = rt.run([
+        OsStr::new("run-all"),
+        OsStr::new("fixtures/run-all"),
+        OsStr::new("--json"),
+        report.as_os_str(),
+        OsStr::new("--junit"),
+        junit.as_os_str(),
+    ])?;
+    assert_eq!(output.status.code().unwrap(), 1);
+    assert!(output.stdout.contains("2 passed; 1 failed"));
+    assert!(output.stdout.contains("FAIL (3)"));
+
+    let report = std::fs::read_to_string(report)?;
+    assert!(report.contains("\"failed\": 1"));
+    let junit = std::fs::read_to_string(junit)?;
+    assert!(junit.contains("tests=\"3\" failures=\"1\""));
+
+    let output = rt.run(["run-all", "fixtures/run-all/**/missing.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 64);
+    assert!(output
+        .stderr
+        .contains("No scripts matched the given targets"));
+
+    let output = rt.run(["play", "run-all", "fixtures/run-all/**/pass.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert!(output.stdout.contains("2 passed; 0 failed"));
+
+    Ok(())
+}
+
+#[test]
+fn watch_mode() -> Result<()> {
+    use std::io::BufReader;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let rt = TestRuntime::new()?;
+    let script = rt.temp_dir("watched.rs");
+    std::fs::write(&script, "fn main() { println!(\"first\"); }")?;
+
+    let mut child = rt
+        .run_with_stdin([OsStr::new("--watch"), script.as_os_str()])
+        .spawn()?;
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in stdout.lines() {
+            let _ = tx.send(line.unwrap());
+        }
+    });
+
+    let timeout = Duration::from_secs(60);
+    assert_eq!(rx.recv_timeout(timeout).unwrap(), "first");
+
+    // make sure the modification time actually changes
+    std::thread::sleep(Duration::from_millis(1100));
+    std::fs::write(&script, "fn main() { println!(\"second\"); }")?;
+    assert_eq!(rx.recv_timeout(timeout).unwrap(), "second");
+
+    child.kill()?;
+    child.wait()?;
+
+    Ok(())
+}
+
+#[test]
+fn message_format_json() -> Result<()> {
+    let rt = TestRuntime::new()?;
+
+    let output = rt.run(["--message-format", "json", "fixtures/hello.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    let events: Vec<serde_json::Value> = output
+        .stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let kinds: Vec<&str> = events
+        .iter()
+        .map(|event| event["event"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            "project-prepared",
+            "build-finished",
+            "program-started",
+            "program-output",
+            "program-exited"
+        ]
+    );
+    assert_eq!(events[3]["line"], "Hello World!");
+    assert_eq!(events[4]["code"], 0);
+
+    let output = rt.run(["--message-format", "json", "fixtures/compile-error.rs"])?;
+    assert_ne!(output.status.code().unwrap(), 0);
+    let error = output
+        .stdout
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .find(|event| event["event"] == "compiler-message")
+        .unwrap();
+    let file = error["message"]["spans"][0]["file_name"].as_str().unwrap();
+    assert_eq!(
+        Path::new(file),
+        std::fs::canonicalize("fixtures/compile-error.rs")?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn ide_setup() -> Result<()> {
+    let rt = TestRuntime::new()?;
+    let output = rt.run([
+        "--ide-setup",
+        "fixtures/multi/entry.rs",
+        "fixtures/multi/hello.rs",
+    ])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+
+    let manifest = output
+        .stdout
+        .lines()
+        .find_map(|line| line.strip_prefix("Linked project: "))
+        .map(PathBuf::from)
+        .unwrap();
+    let content = std::fs::read_to_string(&manifest)?;
+    let entry = std::fs::canonicalize("fixtures/multi/entry.rs")?;
+    assert!(content.contains(&format!("path = {:?}", entry.to_string_lossy())));
+
+    let status = Command::new("cargo")
+        .arg("check")
+        .arg("--quiet")
+        .arg("--manifest-path")
+        .arg(&manifest)
+        .status()?;
+    assert!(status.success());
+
+    Ok(())
+}
+
+#[test]
+fn serve() -> Result<()> {
+    use std::io::BufReader;
+
+    let rt = TestRuntime::new()?;
+    let mut child = rt.run_with_stdin(["serve"]).spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+    let mut next = move || -> serde_json::Value {
+        serde_json::from_str(&stdout.next().unwrap().unwrap()).unwrap()
+    };
+
+    writeln!(
+        stdin,
+        r#"{{"jsonrpc":"2.0","id":1,"method":"listTests","params":{{"file":"fixtures/tests.rs"}}}}"#
+    )?;
+    assert_eq!(next()["result"]["tests"], serde_json::json!(["test_add"]));
+
+    writeln!(
+        stdin,
+        r#"{{"jsonrpc":"2.0","id":2,"method":"run","params":{{"file":"fixtures/hello.rs"}}}}"#
+    )?;
+    let response = loop {
+        let message = next();
+        if message["method"] == "event" && message["params"]["event"] == "program-output" {
+            assert_eq!(message["params"]["line"], "Hello World!");
+        }
+        if message["id"] == 2 {
+            break message;
+        }
+    };
+    assert_eq!(response["result"]["code"], 0);
+
+    let sleep = r#"fn main() { std::thread::sleep(std::time::Duration::from_secs(600)); }"#;
+    writeln!(
+        stdin,
+        "{}",
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 3,
+            "method": "run",
+            "params": { "source": sleep, "name": "sleep" },
+        })
+    )?;
+    while next()["params"]["event"] != "program-started" {}
+    writeln!(
+        stdin,
+        r#"{{"jsonrpc":"2.0","id":4,"method":"cancel","params":{{"id":3}}}}"#
+    )?;
+    let mut responses = Vec::new();
+    while responses.len() < 2 {
+        let message = next();
+        if message.get("result").is_some() {
+            responses.push(message);
+        }
+    }
+    responses.sort_by_key(|response| response["id"].as_i64());
+    assert_eq!(responses[0]["result"]["cancelled"], true);
+    assert_eq!(responses[1]["result"]["cancelled"], true);
+
+    writeln!(
+        stdin,
+        r#"{{"jsonrpc":"2.0","id":5,"method":"cancel","params":{{"id":3}}}}"#
+    )?;
+    assert_eq!(next()["result"]["cancelled"], false);
+
+    writeln!(
+        stdin,
+        r#"{{"jsonrpc":"2.0","id":6,"method":"check","params":{{"source":"fn main() {{}}","name":"../escape","edition":"next"}}}}"#
+    )?;
+    assert_eq!(next()["error"]["code"], -32602);
+
+    drop(stdin);
+    assert!(child.wait()?.success());
+
+    Ok(())
+}
+
+#[test]
+fn lint() -> Result<()> {
+    let rt = TestRuntime::new()?;
+    let output = rt.run(["lint", "fixtures/lint/entry.rs", "fixtures/lint/helper.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 1);
+
+    let locations: Vec<&str> = output
+        .stderr
+        .lines()
+        .filter_map(|line| line.strip_prefix("  --> "))
+        .collect();
+    assert_eq!(
+        locations,
+        [
+            "fixtures/lint/entry.rs:2",
+            "fixtures/lint/entry.rs:3",
+            "fixtures/lint/helper.rs:1"
+        ]
+    );
+    assert!(output.stderr.contains("`itoa` is declared but never used"));
+    assert!(output.stderr.contains("header doesn't parse"));
+    assert!(output
+        .stderr
+        .contains("`rand` is declared again with a different requirement"));
+
+    let output = rt.run(["lint", "fixtures/hello.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert_eq!(output.stderr, "");
+
+    // tokio drives the `async fn main` without being named in the sources
+    let output = rt.run(["lint", "fixtures/lint/async.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert_eq!(output.stderr, "");
+
+    Ok(())
+}
+
+#[test]
+fn bad_header() -> Result<()> {
+    let rt = TestRuntime::new()?;
+    let output = rt.run(["fixtures/bad-header.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 65);
+
+    let lines: Vec<&str> = output.stderr.lines().collect();
+    assert_eq!(
+        lines[0],
+        "error: invalid header: expected an equals, found an identifier"
+    );
+    assert!(lines[1].ends_with("fixtures/bad-header.rs:1:11"));
+    assert_eq!(lines[3], "1 | //# serde 1");
+    assert_eq!(lines[4], "  |           ^");
+    assert_eq!(lines[5], "  = help: did you mean `serde = \"1\"`?");
+
+    Ok(())
+}
+
+#[test]
+fn exit_codes() -> Result<()> {
+    let rt = TestRuntime::new()?;
+
+    let output = rt.run(["--no-such-flag", "fixtures/hello.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 64);
+    assert!(output
+        .stderr
+        .starts_with("error: Found argument '--no-such-flag'"));
+
+    let output = rt.run(["--help"])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+
+    let output = rt.run(["fixtures/compile-error.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 66);
+
+    let output = rt.run(["fixtures/run-all/fail.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 3);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn exit_signal() -> Result<()> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let rt = TestRuntime::new()?;
+    let output = rt.run(["fixtures/abort.rs"])?;
+    assert_eq!(output.status.signal(), Some(6));
+
+    Ok(())
+}
+
+#[test]
+fn feature_nightly() -> Result<()> {
+    let rt = TestRuntime::new()?;
+
+    let installed = match Command::new("rustup").args(["toolchain", "list"]).output() {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).into_owned(),
+        Err(_) => return Ok(()),
+    };
+
+    if installed.lines().any(|line| line.starts_with("nightly-")) {
+        let output: StringOutput = rt
+            .run_with_stdin(["fixtures/feature.rs"])
+            .env_remove("RUSTUP_TOOLCHAIN")
+            .env_remove("RUSTUP_TOOLCHAIN_SOURCE")
+            .output()?
+            .into();
+        assert_eq!(output.status.code().unwrap(), 0);
+        assert!(output
+            .stderr
+            .contains("using the nightly toolchain for #![feature(never_type)]"));
+        assert_eq!(output.stdout, "Ok(1)\n");
+
+        let output: StringOutput = rt
+            .run_with_stdin(["run-all", "fixtures/feature.rs"])
+            .env_remove("RUSTUP_TOOLCHAIN")
+            .env_remove("RUSTUP_TOOLCHAIN_SOURCE")
+            .output()?
+            .into();
+        assert_eq!(output.status.code().unwrap(), 0);
+        assert!(output.stdout.contains("1 passed; 0 failed"));
+    }
+
+    let config = rt.temp_dir("config.toml");
+    std::fs::write(&config, "nightly-toolchain = \"nightly-1999-01-01\"\n")?;
+    let output: StringOutput = rt
+        .run_with_stdin(["fixtures/feature.rs"])
+        .env_remove("RUSTUP_TOOLCHAIN")
+        .env_remove("RUSTUP_TOOLCHAIN_SOURCE")
+        .env("CARGO_PLAY_CONFIG", &config)
+        .output()?
+        .into();
+    assert_eq!(output.status.code().unwrap(), 64);
+    assert!(output
+        .stderr
+        .contains("#![feature(never_type)] needs a nightly toolchain, but \"nightly-1999-01-01\" is not installed"));
+
+    Ok(())
+}
+
+#[test]
+fn emit() -> Result<()> {
+    let rt = TestRuntime::new()?;
+
+    let output = rt.run(["--emit", "mir", "--filter", "add", "fixtures/emit.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert!(output
+        .stdout
+        .starts_with("fn add(_1: u32, _2: u32) -> u32 {\n"));
+    assert!(output.stdout.ends_with("}\n"));
+    assert!(!output.stdout.contains("fn main"));
+
+    let output = rt.run(["--emit", "llvm-ir", "--filter", "add", "fixtures/emit.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert!(output.stdout.starts_with("define internal i32 @"));
+    assert!(output.stdout.contains("::add(i32"));
+
+    let output = rt.run([
+        "--emit",
+        "asm",
+        "--release",
+        "--filter",
+        "add",
+        "fixtures/emit.rs",
+    ])?;
+    assert_eq!(output.status.code().unwrap(), 0);
+    assert!(output.stdout.lines().next().unwrap().ends_with("::add:"));
+    let opt = Options::with_files(vec!["fixtures/emit.rs"]);
+    let project = rt.temp_dir(opt.temp_dirname());
+    assert!(project.join("target").join("release").exists());
+
+    let output = rt.run(["--emit", "asm", "--filter", "sub", "fixtures/emit.rs"])?;
+    assert_eq!(output.status.code().unwrap(), 64);
+    assert!(output.stderr.contains("No function named \"sub\""));
+
+    Ok(())
+}